@@ -1,6 +1,5 @@
 'use strict'
 
-var Knex = require('knex')
 var SqlView = require('./build/Release/binding.node')
 
 module.exports = function SsbDb (logPath, dbPath, secretKey, pubKey) {
@@ -17,29 +16,43 @@ module.exports = function SsbDb (logPath, dbPath, secretKey, pubKey) {
     throw new TypeError('Expected pubKey to be a string')
   }
 
-  var knex = Knex({
-    client: 'sqlite3',
-    useNullAsDefault: true,
-    connection: {
-      filename: dbPath
-    }
-  })
-
   var db = new SqlView(logPath, dbPath, secretKey, pubKey)
+  var knex
 
   var exports = {
     process,
     getLatest: () => db.getLatest(),
-    knex,
+    backlinks: (id) => db.backlinks(id),
+    thread: (rootId) => db.thread(rootId),
+    friends: (id) => db.friends(id),
+    search: (query, opts) => db.search(query, (opts && opts.limit) || 20),
+    profile: (id) => db.profile(id),
     modifiers: require('./modifiers').modifiers,
     strings: require('./modifiers').strings
   }
 
+  // knex and sqlite3 are optional. They're only loaded if you use `sqlView.knex`.
+  Object.defineProperty(exports, 'knex', {
+    enumerable: true,
+    get: function () {
+      if (!knex) {
+        knex = require('knex')({
+          client: 'sqlite3',
+          useNullAsDefault: true,
+          connection: {
+            filename: dbPath
+          }
+        })
+      }
+      return knex
+    }
+  })
+
   return exports
 
   function process (opts) {
-    opts = opts || { chunkSize: -1 }
-    db.process(opts.chunkSize)
+    opts = opts || {}
+    db.process(opts.chunkSize == null ? -1 : opts.chunkSize)
   }
 }
 
@@ -17,7 +17,6 @@ use flumedb::flume_log::FlumeLog;
 use flumedb::offset_log::OffsetLogIter;
 use itertools::Itertools;
 use private_box::SecretKey;
-use ssb_sql_napi::flume_view_sql::queries::back_link_references;
 use ssb_sql_napi::FlumeViewSql;
 
 const NUM_ENTRIES: u32 = 100000;
@@ -35,7 +34,7 @@ fn create_test_db(num_entries: usize, offset_filename: &str, db_filename: &str)
         .chunks(NUM_ENTRIES as usize)
         .into_iter()
         .for_each(|chunk| {
-            view.append_batch(&chunk.collect_vec());
+            view.append_batch(&chunk.collect_vec()).unwrap();
         });
 
     view
@@ -57,7 +56,7 @@ fn flume_view_sql_insert_piets_entire_log(c: &mut Criterion) {
                 .chunks(NUM_ENTRIES as usize)
                 .into_iter()
                 .for_each(|chunk| {
-                    view.append_batch(&chunk.collect_vec());
+                    view.append_batch(&chunk.collect_vec()).unwrap();
                 });
         })
     });
@@ -89,7 +88,7 @@ fn flume_view_sql_insert_piets_entire_log_with_decryption(c: &mut Criterion) {
                     .chunks(NUM_ENTRIES as usize)
                     .into_iter()
                     .for_each(|chunk| {
-                        view.append_batch(&chunk.collect_vec());
+                        view.append_batch(&chunk.collect_vec()).unwrap();
                     });
             })
         },
@@ -116,7 +115,7 @@ fn flume_view_sql_insert(c: &mut Criterion) {
                 .chunks(NUM_ENTRIES as usize)
                 .into_iter()
                 .for_each(|chunk| {
-                    view.append_batch(&chunk.collect_vec());
+                    view.append_batch(&chunk.collect_vec()).unwrap();
                 });
         })
     });
@@ -126,11 +125,12 @@ fn all_messages_by_type(c: &mut Criterion) {
     let offset_filename = "/home/piet/.ssb/flume/log.offset";
     let db_filename = "/tmp/test_messages_by_type.sqlite3";
 
-    let mut view = create_test_db(NUM_ENTRIES as usize, offset_filename, db_filename);
+    let view = create_test_db(NUM_ENTRIES as usize, offset_filename, db_filename);
+    let reader = view.reader().unwrap();
 
     c.bench_function("flumeview all messages by type", move |b| {
         b.iter(|| {
-            let seqs = view.get_seqs_by_type("post").unwrap();
+            let seqs = reader.get_seqs_by_type("post").unwrap();
         })
     });
 }
@@ -139,15 +139,13 @@ fn backlink_ref(c: &mut Criterion) {
     let offset_filename = "/home/piet/.ssb/flume/log.offset";
     let db_filename = "/tmp/test_messages_by_type.sqlite3";
 
-    let mut view = create_test_db(NUM_ENTRIES as usize, offset_filename, db_filename);
+    let view = create_test_db(NUM_ENTRIES as usize, offset_filename, db_filename);
+    let reader = view.reader().unwrap();
 
     c.bench_function("back_link_references", move |b| {
         b.iter(|| {
-            let links = back_link_references(
-                &view.connection,
-                "%ZEuQdC7OBxDgRg2Vv/VgjArRIpE5YwIMo6ufXqaWaGg=.sha256",
-                0.0,
-            );
+            let links = reader
+                .back_link_references("%ZEuQdC7OBxDgRg2Vv/VgjArRIpE5YwIMo6ufXqaWaGg=.sha256", 0.0);
             assert_eq!(links.unwrap().len(), 1);
         })
     });
@@ -157,11 +155,12 @@ fn all_messages_by_author(c: &mut Criterion) {
     let db_filename = "/tmp/test_messages_by_author.sqlite3";
     let author_key = "@U5GvOKP/YUza9k53DSXxT0mk3PIrnyAmessvNfZl5E0=.ed25519";
 
-    let mut view = create_test_db(NUM_ENTRIES as usize, offset_filename, db_filename);
+    let view = create_test_db(NUM_ENTRIES as usize, offset_filename, db_filename);
+    let reader = view.reader().unwrap();
 
     c.bench_function("flumeview all messages by author", move |b| {
         b.iter(|| {
-            let seqs = view.get_seqs_by_author(author_key).unwrap();
+            let seqs = reader.get_seqs_by_author(author_key).unwrap();
         })
     });
 }
@@ -1,47 +1,733 @@
-extern crate flumedb;
+extern crate base64;
+extern crate env_logger;
+#[macro_use]
+extern crate failure;
+extern crate jsonrpc_pubsub;
 extern crate jsonrpc_tcp_server;
-extern crate r2d2;
-extern crate r2d2_sqlite;
-extern crate rusqlite;
+#[macro_use]
+extern crate log;
+extern crate private_box;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate ssb_sql_napi;
 
-use jsonrpc_tcp_server::jsonrpc_core::*;
-use r2d2_sqlite::SqliteConnectionManager;
-use std::time::{Duration, Instant};
+use failure::Error;
+use jsonrpc_pubsub::{PubSubHandler, Session, Sink, Subscriber, SubscriptionId};
+use jsonrpc_tcp_server::jsonrpc_core::futures::Future;
+use jsonrpc_tcp_server::jsonrpc_core::{
+    Error as RpcError, ErrorCode, MetaIoHandler, Metadata, Params, Value,
+};
+use jsonrpc_tcp_server::RequestContext;
+use private_box::SecretKey;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use flumedb::Sequence;
-use rusqlite::NO_PARAMS;
+use ssb_sql_napi::flume_view_sql::queries::{FeedOrder, LiveQuery};
+use ssb_sql_napi::flume_view_sql::{scan_blob_store, FlumeViewSqlOptions, SqlReader};
+use ssb_sql_napi::FlumeViewSql;
 
-fn main() {
-    let manager = SqliteConnectionManager::file("/tmp/patchwork.sqlite3");
-    let pool = r2d2::Pool::new(manager).unwrap();
+type Meta = Arc<Session>;
+
+const USAGE: &str = "
+Usage: rpc-server [options]
+
+Options:
+    --config <path>       Json config file. Options given on the command line override it.
+    --db <path>           Path to the sqlite db. Created if it doesn't exist.
+    --log <path>          Path to the flume offset log.
+    --bind <address>      Address to listen on. Defaults to 127.0.0.1:9876.
+    --pub-key <id>        The feed id of the local identity.
+    --secret-key <key>    Base64 private box secret key used to decrypt private messages.
+    --poll-ms <ms>        How long to wait before checking the log for new messages. Defaults to 1000.
+    --validate            Check the signature and hash of every message as it's indexed.
+    --exclude-blocked     Leave the messages of feeds the local identity blocks out of the db.
+    --help                Show this message.
+";
+
+const DEFAULT_BIND_ADDRESS: &str = "127.0.0.1:9876";
+const DEFAULT_POLL_MS: u64 = 1000;
+const DEFAULT_LIMIT: i64 = 20;
+const DEFAULT_HOPS: i64 = 1;
+const INDEX_CHUNK_SIZE: i64 = 10000;
+
+// Server errors are in the range json-rpc reserves for implementations.
+const QUERY_FAILED_ERROR_CODE: i64 = -32000;
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+struct Config {
+    db_path: Option<String>,
+    log_path: Option<String>,
+    bind_address: Option<String>,
+    pub_key: Option<String>,
+    secret_key: Option<String>,
+    poll_ms: Option<u64>,
+    validate_messages: Option<bool>,
+    exclude_blocked_feeds: Option<bool>,
+}
+
+impl Config {
+    fn from_args(args: &[String]) -> Result<Config, String> {
+        let config_path = args
+            .iter()
+            .position(|arg| arg == "--config")
+            .map(|index| arg_value(args, index));
+
+        let mut config = match config_path {
+            Some(path) => Config::from_file(&path?)?,
+            None => Config::default(),
+        };
+
+        let mut index = 0;
+        while index < args.len() {
+            match args[index].as_str() {
+                "--config" => (),
+                "--validate" => {
+                    config.validate_messages = Some(true);
+                    index += 1;
+                    continue;
+                }
+                "--exclude-blocked" => {
+                    config.exclude_blocked_feeds = Some(true);
+                    index += 1;
+                    continue;
+                }
+                "--db" => config.db_path = Some(arg_value(args, index)?),
+                "--log" => config.log_path = Some(arg_value(args, index)?),
+                "--bind" => config.bind_address = Some(arg_value(args, index)?),
+                "--pub-key" => config.pub_key = Some(arg_value(args, index)?),
+                "--secret-key" => config.secret_key = Some(arg_value(args, index)?),
+                "--poll-ms" => {
+                    let poll_ms = arg_value(args, index)?;
+                    config.poll_ms = Some(
+                        poll_ms
+                            .parse()
+                            .map_err(|_| format!("Invalid --poll-ms: {}", poll_ms))?,
+                    )
+                }
+                arg => return Err(format!("Unknown option: {}", arg)),
+            }
+            index += 2;
+        }
+
+        Ok(config)
+    }
+
+    fn from_file(path: &str) -> Result<Config, String> {
+        let file = std::fs::File::open(path)
+            .map_err(|err| format!("Couldn't open config file {}: {}", path, err))?;
+
+        serde_json::from_reader(file)
+            .map_err(|err| format!("Couldn't parse config file {}: {}", path, err))
+    }
+}
+
+fn arg_value(args: &[String], index: usize) -> Result<String, String> {
+    args.get(index + 1)
+        .cloned()
+        .ok_or_else(|| format!("Missing value for {}", args[index]))
+}
+
+fn secret_keys_from_config(config: &Config) -> Result<Vec<SecretKey>, String> {
+    match config.secret_key {
+        Some(ref secret_key) => base64::decode(secret_key)
+            .ok()
+            .and_then(|bytes| SecretKey::from_slice(&bytes))
+            .map(|secret_key| vec![secret_key])
+            .ok_or_else(|| "Invalid secret key".to_string()),
+        None => Ok(Vec::new()),
+    }
+}
 
-    let mut io = MetaIoHandler::<()>::default();
+#[derive(Deserialize)]
+struct IdParams {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct SearchParams {
+    query: String,
+    limit: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct FeedParams {
+    author: String,
+    before: Option<i64>,
+    limit: Option<i64>,
+}
+
+// `id` is the author for `author_feed` and `mentions_feed`. `before` and `after` are cursors from
+// a previous page.
+#[derive(Deserialize, Default)]
+struct FeedPageParams {
+    id: Option<String>,
+    content_type: Option<String>,
+    order: Option<FeedOrder>,
+    before: Option<String>,
+    after: Option<String>,
+    limit: Option<i64>,
+}
+
+impl FeedPageParams {
+    fn order(&self) -> FeedOrder {
+        self.order.unwrap_or(FeedOrder::SortTime)
+    }
+
+    fn before(&self) -> Option<&str> {
+        self.before.as_ref().map(|before| before.as_str())
+    }
+
+    fn after(&self) -> Option<&str> {
+        self.after.as_ref().map(|after| after.as_str())
+    }
+
+    fn limit(&self) -> i64 {
+        self.limit.unwrap_or(DEFAULT_LIMIT)
+    }
+}
+
+#[derive(Deserialize, Default)]
+struct FeedTipsParams {
+    active_since: Option<f64>,
+}
+
+#[derive(Deserialize, Default)]
+struct GatheringsParams {
+    after: Option<f64>,
+    limit: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct HopsParams {
+    id: String,
+    hops: Option<i64>,
+}
 
-    io.add_method("get_latest", move |params| {
+// `blobs` are the ids of the locally stored blobs. Or give the `path` of a blob store to scan.
+#[derive(Deserialize)]
+struct BlobReportParams {
+    id: String,
+    hops: Option<i64>,
+    blobs: Option<Vec<String>>,
+    path: Option<String>,
+}
+
+// Live queries that clients have subscribed to. Each one has a sink that sends notifications back
+// over the client's tcp connection.
+#[derive(Default)]
+struct Subscriptions {
+    next_id: AtomicUsize,
+    live_queries: Mutex<HashMap<u64, (LiveQuery, Sink)>>,
+}
+
+impl Subscriptions {
+    fn subscribe(&self, query: LiveQuery, subscriber: Subscriber) {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst) as u64;
+
+        if let Ok(sink) = subscriber.assign_id(SubscriptionId::Number(id)) {
+            debug!("subscription {} added: {:?}", id, query);
+            self.live_queries.lock().unwrap().insert(id, (query, sink));
+        }
+    }
+
+    fn unsubscribe(&self, id: &SubscriptionId) -> bool {
+        match id {
+            SubscriptionId::Number(id) => self.live_queries.lock().unwrap().remove(id).is_some(),
+            _ => false,
+        }
+    }
+
+    // Called by the indexer once messages with a flume_seq in (after_seq, up_to_seq] are committed.
+    // The subscriptions are copied out of the lock first, so a slow client doesn't stop others
+    // subscribing or unsubscribing while it's sent to.
+    fn notify(&self, reader: &SqlReader, after_seq: i64, up_to_seq: i64) {
+        let live_queries: Vec<(u64, LiveQuery, Sink)> = self
+            .live_queries
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, (query, sink))| (*id, query.clone(), sink.clone()))
+            .collect();
+        let mut closed = Vec::new();
+
+        for (id, query, sink) in live_queries.iter() {
+            let messages = match reader.live_query_results(query, after_seq, up_to_seq) {
+                Ok(messages) => messages,
+                Err(err) => {
+                    error!("live query for subscription {} failed: {}", id, err);
+                    continue;
+                }
+            };
+
+            for message in messages {
+                let mut params = serde_json::Map::new();
+                params.insert("subscription".to_string(), Value::from(*id));
+                params.insert(
+                    "result".to_string(),
+                    serde_json::to_value(message).unwrap_or(Value::Null),
+                );
+
+                if sink.notify(Params::Map(params)).wait().is_err() {
+                    closed.push(*id);
+                    break;
+                }
+            }
+        }
+
+        let mut live_queries = self.live_queries.lock().unwrap();
+        for id in closed {
+            debug!("subscription {} closed", id);
+            live_queries.remove(&id);
+        }
+    }
+}
+
+fn now_millis() -> f64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    now.as_secs() as f64 * 1000.0 + f64::from(now.subsec_millis())
+}
+
+fn query_error(err: Error) -> RpcError {
+    RpcError {
+        code: ErrorCode::ServerError(QUERY_FAILED_ERROR_CODE),
+        message: "Query failed".to_string(),
+        data: Some(Value::String(err.to_string())),
+    }
+}
+
+fn required_param<'a>(name: &str, param: &'a Option<String>) -> Result<&'a str, Error> {
+    param
+        .as_ref()
+        .map(|param| param.as_str())
+        .ok_or_else(|| format_err!("Missing {}", name))
+}
+
+fn to_rpc_result<T: Serialize>(result: Result<T, Error>) -> Result<Value, RpcError> {
+    result
+        .and_then(|value| serde_json::to_value(value).map_err(|err| err.into()))
+        .map_err(query_error)
+}
+
+// Registers a method and logs how long each call takes.
+fn add_query_method<M, F>(io: &mut MetaIoHandler<M>, name: &'static str, method: F)
+where
+    M: Metadata,
+    F: Fn(Params) -> Result<Value, RpcError> + Send + Sync + 'static,
+{
+    io.add_method(name, move |params: Params| {
         let now = Instant::now();
-        println!("handle get_latest, params: {:?}", params);
-        let connection = pool.clone().get().unwrap();
-        let mut stmt = connection
-            .prepare_cached("SELECT MAX(flume_seq) FROM messages_raw")
-            .unwrap();
+        let result = method(params);
+        debug!("handled {} in {}us", name, now.elapsed().as_micros());
+        result
+    });
+}
+
+fn create_io_handler<M: Metadata>(reader: SqlReader) -> MetaIoHandler<M> {
+    let mut io = MetaIoHandler::<M>::default();
+
+    let method_reader = reader.clone();
+    add_query_method(&mut io, "get_latest", move |_params| {
+        to_rpc_result(method_reader.get_latest())
+    });
+
+    let method_reader = reader.clone();
+    add_query_method(&mut io, "thread", move |params| {
+        let params: IdParams = params.parse()?;
+        to_rpc_result(method_reader.thread(&params.id))
+    });
+
+    let method_reader = reader.clone();
+    add_query_method(&mut io, "backlinks", move |params| {
+        let params: IdParams = params.parse()?;
+        to_rpc_result(method_reader.back_link_references(&params.id, 0.0))
+    });
+
+    let method_reader = reader.clone();
+    add_query_method(&mut io, "friends", move |params| {
+        let params: IdParams = params.parse()?;
+        to_rpc_result(method_reader.friends(&params.id))
+    });
+
+    let method_reader = reader.clone();
+    add_query_method(&mut io, "search", move |params| {
+        let params: SearchParams = params.parse()?;
+        let limit = params.limit.unwrap_or(DEFAULT_LIMIT);
+        to_rpc_result(method_reader.search(&params.query, limit))
+    });
+
+    let method_reader = reader.clone();
+    add_query_method(&mut io, "feed", move |params| {
+        let params: FeedParams = params.parse()?;
+        let limit = params.limit.unwrap_or(DEFAULT_LIMIT);
+        to_rpc_result(method_reader.feed(&params.author, params.before, limit))
+    });
+
+    let method_reader = reader.clone();
+    add_query_method(&mut io, "profile", move |params| {
+        let params: IdParams = params.parse()?;
+        to_rpc_result(method_reader.profile(&params.id))
+    });
+
+    let method_reader = reader.clone();
+    add_query_method(&mut io, "feed_integrity", move |params| {
+        let params: IdParams = params.parse()?;
+        to_rpc_result(method_reader.feed_integrity(&params.id))
+    });
+
+    let method_reader = reader.clone();
+    add_query_method(&mut io, "forked_feeds", move |_params| {
+        to_rpc_result(method_reader.forked_feeds())
+    });
+
+    let method_reader = reader.clone();
+    add_query_method(&mut io, "feed_tips", move |params| {
+        let params: FeedTipsParams = match params {
+            Params::None => FeedTipsParams::default(),
+            params => params.parse()?,
+        };
+        to_rpc_result(method_reader.feed_tips(params.active_since))
+    });
+
+    let method_reader = reader.clone();
+    add_query_method(&mut io, "repos_by_author", move |params| {
+        let params: IdParams = params.parse()?;
+        to_rpc_result(method_reader.repos_by_author(&params.id))
+    });
+
+    let method_reader = reader.clone();
+    add_query_method(&mut io, "repo_refs", move |params| {
+        let params: IdParams = params.parse()?;
+        to_rpc_result(method_reader.repo_refs(&params.id))
+    });
+
+    let method_reader = reader.clone();
+    add_query_method(&mut io, "open_issues", move |params| {
+        let params: IdParams = params.parse()?;
+        to_rpc_result(method_reader.open_issues(&params.id))
+    });
+
+    let method_reader = reader.clone();
+    add_query_method(&mut io, "upcoming_gatherings", move |params| {
+        let params: GatheringsParams = match params {
+            Params::None => GatheringsParams::default(),
+            params => params.parse()?,
+        };
+        let after = params.after.unwrap_or_else(now_millis);
+        let limit = params.limit.unwrap_or(DEFAULT_LIMIT);
+        to_rpc_result(method_reader.upcoming_gatherings(after, limit))
+    });
+
+    let method_reader = reader.clone();
+    add_query_method(&mut io, "gathering_attendees", move |params| {
+        let params: IdParams = params.parse()?;
+        to_rpc_result(method_reader.gathering_attendees(&params.id))
+    });
+
+    let method_reader = reader.clone();
+    add_query_method(&mut io, "ranked_pubs", move |params| {
+        let params: IdParams = params.parse()?;
+        to_rpc_result(method_reader.ranked_pubs(&params.id))
+    });
+
+    let method_reader = reader.clone();
+    add_query_method(&mut io, "my_tags", move |_params| {
+        to_rpc_result(method_reader.my_tags())
+    });
+
+    let method_reader = reader.clone();
+    add_query_method(&mut io, "messages_with_tag", move |params| {
+        let params: IdParams = params.parse()?;
+        to_rpc_result(method_reader.messages_with_tag(&params.id))
+    });
+
+    let method_reader = reader.clone();
+    add_query_method(&mut io, "tags_on_message", move |params| {
+        let params: IdParams = params.parse()?;
+        to_rpc_result(method_reader.tags_on_message(&params.id))
+    });
 
-        let seq: i64 = stmt
-            .query_row(NO_PARAMS, |row| {
-                let res: i64 = row.get_checked(0).unwrap_or(0);
-                res as Sequence
-            })
-            .unwrap() as i64;
+    let method_reader = reader.clone();
+    add_query_method(&mut io, "blobs_within_hops", move |params| {
+        let params: HopsParams = params.parse()?;
+        let hops = params.hops.unwrap_or(DEFAULT_HOPS);
+        to_rpc_result(method_reader.blobs_within_hops(&params.id, hops))
+    });
+
+    let method_reader = reader.clone();
+    add_query_method(&mut io, "my_blobs", move |_params| {
+        to_rpc_result(method_reader.my_blobs())
+    });
+
+    let method_reader = reader.clone();
+    add_query_method(&mut io, "blob_report", move |params| {
+        let params: BlobReportParams = params.parse()?;
+        let hops = params.hops.unwrap_or(DEFAULT_HOPS);
+        let local_blobs = match (params.blobs, params.path) {
+            (Some(blobs), _) => Ok(blobs),
+            (None, Some(path)) => scan_blob_store(&path),
+            (None, None) => Err(format_err!("Either blobs or path is required")),
+        };
+        to_rpc_result(
+            local_blobs.and_then(|blobs| method_reader.blob_report(&params.id, hops, &blobs)),
+        )
+    });
+
+    let method_reader = reader.clone();
+    add_query_method(&mut io, "hidden_content", move |_params| {
+        to_rpc_result(method_reader.hidden_content())
+    });
+
+    let method_reader = reader.clone();
+    add_query_method(&mut io, "public_feed", move |params| {
+        let params: FeedPageParams = match params {
+            Params::None => FeedPageParams::default(),
+            params => params.parse()?,
+        };
+        to_rpc_result(method_reader.public_feed(
+            params.order(),
+            params.before(),
+            params.after(),
+            params.limit(),
+        ))
+    });
 
-        println!("{}", now.elapsed().as_micros());
-        Ok(Value::Number(seq.into()))
+    let method_reader = reader.clone();
+    add_query_method(&mut io, "author_feed", move |params| {
+        let params: FeedPageParams = params.parse()?;
+        to_rpc_result(required_param("id", &params.id).and_then(|author| {
+            method_reader.author_feed(
+                author,
+                params.order(),
+                params.before(),
+                params.after(),
+                params.limit(),
+            )
+        }))
     });
 
-    let handler = ::std::thread::spawn(move || {
-        let _server = jsonrpc_tcp_server::ServerBuilder::new(io)
-            .start(&"0.0.0.0:9876".parse().unwrap())
-            .expect("Server should start ok")
-            .wait();
+    let method_reader = reader.clone();
+    add_query_method(&mut io, "type_feed", move |params| {
+        let params: FeedPageParams = params.parse()?;
+        let content_type = required_param("content_type", &params.content_type);
+        to_rpc_result(content_type.and_then(|content_type| {
+            method_reader.type_feed(
+                content_type,
+                params.order(),
+                params.before(),
+                params.after(),
+                params.limit(),
+            )
+        }))
     });
 
-    handler.join().unwrap();
+    let method_reader = reader;
+    add_query_method(&mut io, "mentions_feed", move |params| {
+        let params: FeedPageParams = params.parse()?;
+        to_rpc_result(required_param("id", &params.id).and_then(|author| {
+            method_reader.mentions_feed(
+                author,
+                params.order(),
+                params.before(),
+                params.after(),
+                params.limit(),
+            )
+        }))
+    });
+
+    io
+}
+
+fn create_pubsub_handler(
+    reader: SqlReader,
+    subscriptions: Arc<Subscriptions>,
+) -> PubSubHandler<Meta> {
+    let mut io = PubSubHandler::new(create_io_handler(reader));
+
+    let subscribe_subscriptions = subscriptions.clone();
+    io.add_subscription(
+        "live_query",
+        (
+            "subscribe_live_query",
+            move |params: Params, _meta: Meta, subscriber: Subscriber| match params.parse() {
+                Ok(query) => subscribe_subscriptions.subscribe(query, subscriber),
+                Err(err) => subscriber.reject(err).unwrap_or(()),
+            },
+        ),
+        (
+            "unsubscribe_live_query",
+            move |id: SubscriptionId, _meta: Option<Meta>| -> Result<Value, RpcError> {
+                Ok(Value::Bool(subscriptions.unsubscribe(&id)))
+            },
+        ),
+    );
+
+    io
+}
+
+// Plugins that were rebuilt are caught up while there's nothing new in the log.
+fn catch_up_or_sleep(view: &mut FlumeViewSql, poll_interval: Duration) {
+    match view.catch_up_plugins(INDEX_CHUNK_SIZE) {
+        Ok(0) => std::thread::sleep(poll_interval),
+        Ok(num_processed) => debug!(
+            "caught up {} messages in plugins {:?}",
+            num_processed,
+            view.lagging_plugins()
+        ),
+        Err(err) => {
+            error!("catching up plugins failed: {}", err);
+            std::thread::sleep(poll_interval)
+        }
+    }
+}
+
+fn spawn_indexer(
+    mut view: FlumeViewSql,
+    log_path: String,
+    poll_interval: Duration,
+    reader: SqlReader,
+    subscriptions: Arc<Subscriptions>,
+) -> std::thread::JoinHandle<()> {
+    std::thread::spawn(move || loop {
+        let now = Instant::now();
+        let before = view.get_latest();
+
+        match view.process(&log_path, INDEX_CHUNK_SIZE) {
+            Ok(0) => catch_up_or_sleep(&mut view, poll_interval),
+            Ok(num_processed) => {
+                debug!(
+                    "indexed {} messages in {}ms",
+                    num_processed,
+                    now.elapsed().as_millis()
+                );
+
+                if let (Ok(before), Ok(after)) = (before, view.get_latest()) {
+                    // An empty view also has a latest of 0, so include the entry at 0.
+                    let after_seq = if before == 0 { -1 } else { before as i64 };
+                    subscriptions.notify(&reader, after_seq, after as i64);
+                }
+            }
+            Err(err) => {
+                error!("indexing {} failed: {}", log_path, err);
+                std::thread::sleep(poll_interval)
+            }
+        }
+    })
+}
+
+fn run(config: Config) -> Result<(), Error> {
+    let db_path = config
+        .db_path
+        .clone()
+        .ok_or_else(|| format_err!("Missing --db"))?;
+    let log_path = config
+        .log_path
+        .clone()
+        .ok_or_else(|| format_err!("Missing --log"))?;
+    let bind_address: SocketAddr = config
+        .bind_address
+        .clone()
+        .unwrap_or_else(|| DEFAULT_BIND_ADDRESS.to_string())
+        .parse()?;
+    let pub_key = config.pub_key.clone().unwrap_or_default();
+    let poll_interval = Duration::from_millis(config.poll_ms.unwrap_or(DEFAULT_POLL_MS));
+    let secret_keys = secret_keys_from_config(&config).map_err(|err| format_err!("{}", err))?;
+
+    let options = FlumeViewSqlOptions {
+        validate_messages: config.validate_messages.unwrap_or(false),
+        exclude_blocked_feeds: config.exclude_blocked_feeds.unwrap_or(false),
+        ..FlumeViewSqlOptions::default()
+    };
+    let view = FlumeViewSql::new_with_options(&db_path, secret_keys, &pub_key, options)?;
+    let reader = view.reader()?;
+    let subscriptions = Arc::new(Subscriptions::default());
+
+    spawn_indexer(
+        view,
+        log_path,
+        poll_interval,
+        reader.clone(),
+        subscriptions.clone(),
+    );
+
+    // Each connection gets a session, so subscriptions can send notifications back over it.
+    let server = jsonrpc_tcp_server::ServerBuilder::with_meta_extractor(
+        create_pubsub_handler(reader, subscriptions),
+        |context: &RequestContext| Arc::new(Session::new(context.sender.clone())),
+    )
+    .start(&bind_address)?;
+
+    info!("listening on {}", bind_address);
+    server.wait();
+
+    Ok(())
+}
+
+fn main() {
+    env_logger::init();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    if args.iter().any(|arg| arg == "--help") {
+        println!("{}", USAGE);
+        return;
+    }
+
+    let config = match Config::from_args(&args) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}\n{}", err, USAGE);
+            std::process::exit(1);
+        }
+    };
+
+    if let Err(err) = run(config) {
+        error!("{}", err);
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use jsonrpc_tcp_server::jsonrpc_core::futures::sync::mpsc;
+    use jsonrpc_tcp_server::jsonrpc_core::futures::Stream;
+
+    #[test]
+    fn subscribe_live_query() {
+        let filename = "/tmp/test_rpc_subscribe_live_query.sqlite3";
+        std::fs::remove_file(filename).unwrap_or(());
+
+        let mut view = FlumeViewSql::new(filename, Vec::new(), "").unwrap();
+        let reader = view.reader().unwrap();
+        let subscriptions = Arc::new(Subscriptions::default());
+        let io = create_pubsub_handler(reader.clone(), subscriptions.clone());
+
+        let (sender, receiver) = mpsc::channel(8);
+        let session: Meta = Arc::new(Session::new(sender));
+
+        let request = r#"{"jsonrpc": "2.0", "id": 1, "method": "subscribe_live_query", "params": {"type": "messages_of_type", "content_type": "post"}}"#;
+        let response = io.handle_request_sync(request, session.clone()).unwrap();
+        let response: Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["result"], 0);
+
+        let item = r#"{"key": "%a", "value": {"author": "@QlCTpvY7p9ty2yOFrv1WU1AE88aoQc4Y7wYal7PFc+w=.ed25519", "sequence": 1, "timestamp": 1000.0, "content": {"type": "post", "text": "hi"}}, "timestamp": 1000.0}"#;
+        view.append_batch(&[(0, item.as_bytes().to_vec())]).unwrap();
+        subscriptions.notify(&reader, -1, 0);
+
+        let notification = receiver.wait().next().unwrap().unwrap();
+        let notification: Value = serde_json::from_str(&notification).unwrap();
+        assert_eq!(notification["method"], "live_query");
+        assert_eq!(notification["params"]["subscription"], 0);
+        assert_eq!(notification["params"]["result"]["key"], "%a");
+    }
 }
@@ -0,0 +1,490 @@
+extern crate base64;
+#[macro_use]
+extern crate failure;
+extern crate private_box;
+extern crate serde;
+extern crate serde_json;
+extern crate ssb_sql_napi;
+
+use failure::Error;
+use private_box::SecretKey;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::BufWriter;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use ssb_sql_napi::flume_view_sql::queries::FeedOrder;
+use ssb_sql_napi::flume_view_sql::{
+    scan_blob_store, ExportFilter, ExportFormat, ExportTable, FlumeViewSqlOptions, HiddenContent,
+};
+use ssb_sql_napi::FlumeViewSql;
+
+const USAGE: &str = "
+Usage: ssb-sql <command> [options] [args]
+
+Commands:
+    index                 Index new entries from the offset log and catch up rebuilt plugins, showing
+                          progress.
+    status                Show how far the view is behind the log and how many rows each table has.
+    query <name> <args>   Run a query and print the results as json. Queries are:
+                              thread <root id>
+                              backlinks <id>
+                              friends <feed id>
+                              search <query>
+                              feed <feed id>
+                              profile <feed id>
+                              feed-integrity <feed id>
+                              forks
+                              feeds
+                              repos <feed id>
+                              repo-refs <repo id>
+                              open-issues <repo id>
+                              gatherings
+                              attendees <gathering id>
+                              pubs <feed id>
+                              my-tags
+                              tagged <tag id>
+                              tags <message id>
+                              blobs <feed id> [--hops <n>]
+                              my-blobs
+                              blob-report <feed id> --blobs <path> [--hops <n>]
+                              hidden
+                              public-feed
+                              author-feed <feed id>
+                              type-feed <type>
+                              mentions <feed id>
+    export <table>        Stream a table to stdout. Tables are messages, links, contacts_raw, votes_raw
+                          and abouts.
+    export-log <path>     Write the original entries of the indexed messages to a new offset log.
+                          Accepts the same filters as export.
+    check                 Run an integrity check on the db.
+    rebuild               Delete the db and index the whole offset log again.
+    hide <kind> <id>      Hide a message, author or blob from the view. Kinds are message, author and
+                          blob. Add --purge to delete the hidden messages' content too.
+    unhide <kind> <id>    Stop hiding a message, author or blob.
+
+Options:
+    --db <path>           Path to the sqlite db. Required by every command.
+    --log <path>          Path to the flume offset log. Required by index, status and rebuild.
+    --pub-key <id>        The feed id of the local identity.
+    --secret-key <key>    Base64 private box secret key used to decrypt private messages.
+    --chunk-size <n>      How many entries to index between progress updates. Defaults to 10000.
+    --limit <n>           The maximum number of results for search, feeds and gatherings. Defaults to
+                          20.
+    --before <seq>        The flume_seq to page back from for feed, or the cursor to page back from
+                          for public-feed, author-feed, type-feed and mentions.
+    --after <ms>          The time gatherings have to start after. Defaults to now. For public-feed,
+                          author-feed, type-feed and mentions, the cursor to get newer messages
+                          after.
+    --order <order>       Order public-feed, author-feed, type-feed and mentions by sort_time,
+                          flume_seq or asserted_time. Defaults to sort_time.
+    --hops <n>            How many follows away from the feed blobs can be. Defaults to 1.
+    --blobs <path>        The blob store to check, like ~/.ssb/blobs.
+    --format <format>     The export format, ndjson or csv. Defaults to ndjson.
+    --type <type>         Only export rows from messages of this content type.
+    --author <feed id>    Only export rows by this author.
+    --min-seq <seq>       Only export rows from messages with a flume_seq of at least this.
+    --max-seq <seq>       Only export rows from messages with a flume_seq of at most this.
+    --validate            Make index and rebuild check the signature and hash of every message.
+    --exclude-blocked     Leave the messages of feeds the local identity blocks out of the db.
+    --recover             Make check move a corrupted db aside and create an empty one.
+    --purge               Make hide delete the content of the hidden messages from the db.
+";
+
+const DEFAULT_CHUNK_SIZE: i64 = 10000;
+const DEFAULT_LIMIT: i64 = 20;
+const DEFAULT_HOPS: i64 = 1;
+
+struct Args {
+    command: String,
+    positional: Vec<String>,
+    options: HashMap<String, String>,
+    flags: Vec<String>,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Args, Error> {
+        let mut iter = args.iter();
+        let command = iter
+            .next()
+            .cloned()
+            .ok_or_else(|| format_err!("Missing command"))?;
+
+        let mut positional = Vec::new();
+        let mut options = HashMap::new();
+        let mut flags = Vec::new();
+
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--recover" | "--validate" | "--purge" | "--exclude-blocked" => {
+                    flags.push(arg.clone())
+                }
+                option if option.starts_with("--") => {
+                    let value = iter
+                        .next()
+                        .ok_or_else(|| format_err!("Missing value for {}", option))?;
+                    options.insert(option.trim_start_matches("--").to_string(), value.clone());
+                }
+                _ => positional.push(arg.clone()),
+            }
+        }
+
+        Ok(Args {
+            command,
+            positional,
+            options,
+            flags,
+        })
+    }
+
+    fn option(&self, name: &str) -> Option<&str> {
+        self.options.get(name).map(|value| value.as_str())
+    }
+
+    fn required_option(&self, name: &str) -> Result<&str, Error> {
+        self.option(name)
+            .ok_or_else(|| format_err!("Missing --{}", name))
+    }
+
+    fn int_option(&self, name: &str, default: i64) -> Result<i64, Error> {
+        match self.option(name) {
+            Some(value) => value
+                .parse()
+                .map_err(|_| format_err!("Invalid --{}: {}", name, value)),
+            None => Ok(default),
+        }
+    }
+
+    fn optional_int_option(&self, name: &str) -> Result<Option<i64>, Error> {
+        match self.option(name) {
+            Some(_) => self.int_option(name, 0).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn positional(&self, index: usize, name: &str) -> Result<&str, Error> {
+        self.positional
+            .get(index)
+            .map(|value| value.as_str())
+            .ok_or_else(|| format_err!("Missing {}", name))
+    }
+
+    fn has_flag(&self, name: &str) -> bool {
+        self.flags.iter().any(|flag| flag == name)
+    }
+
+    fn open_view(&self) -> Result<FlumeViewSql, Error> {
+        let secret_keys = match self.option("secret-key") {
+            Some(secret_key) => base64::decode(secret_key)
+                .ok()
+                .and_then(|bytes| SecretKey::from_slice(&bytes))
+                .map(|secret_key| vec![secret_key])
+                .ok_or_else(|| format_err!("Invalid secret key"))?,
+            None => Vec::new(),
+        };
+
+        let options = FlumeViewSqlOptions {
+            validate_messages: self.has_flag("--validate"),
+            exclude_blocked_feeds: self.has_flag("--exclude-blocked"),
+            ..FlumeViewSqlOptions::default()
+        };
+
+        FlumeViewSql::new_with_options(
+            self.required_option("db")?,
+            secret_keys,
+            self.option("pub-key").unwrap_or(""),
+            options,
+        )
+    }
+}
+
+fn print_json<T: Serialize>(value: &T) -> Result<(), Error> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+fn log_length(log_path: &str) -> Result<u64, Error> {
+    std::fs::metadata(log_path)
+        .map(|metadata| metadata.len())
+        .map_err(|err| err.into())
+}
+
+fn index(view: &mut FlumeViewSql, args: &Args) -> Result<(), Error> {
+    let log_path = args.required_option("log")?;
+    let chunk_size = args.int_option("chunk-size", DEFAULT_CHUNK_SIZE)?;
+    let log_length = log_length(log_path)?;
+
+    let now = Instant::now();
+    let mut total = 0;
+
+    loop {
+        let num_processed = view.process(log_path, chunk_size)?;
+        if num_processed == 0 {
+            break;
+        }
+        total += num_processed;
+
+        let latest = view.get_latest()?;
+        eprintln!(
+            "indexed {} entries, at {} of {} ({:.1}%)",
+            total,
+            latest,
+            log_length,
+            percentage(latest, log_length)
+        );
+    }
+
+    loop {
+        let lagging_plugins = view.lagging_plugins().to_vec();
+        let num_processed = view.catch_up_plugins(chunk_size)?;
+        if num_processed == 0 {
+            break;
+        }
+        eprintln!(
+            "caught up {} messages in plugins {}",
+            num_processed,
+            lagging_plugins.join(", ")
+        );
+    }
+
+    eprintln!(
+        "done, indexed {} entries in {:.1}s",
+        total,
+        now.elapsed().as_millis() as f64 / 1000.0
+    );
+
+    Ok(())
+}
+
+fn percentage(latest: u64, log_length: u64) -> f64 {
+    if log_length == 0 {
+        return 100.0;
+    }
+    latest as f64 / log_length as f64 * 100.0
+}
+
+fn status(view: &FlumeViewSql, args: &Args) -> Result<(), Error> {
+    let log_length = log_length(args.required_option("log")?)?;
+    let latest = view.get_latest()?;
+
+    println!(
+        "latest seq: {} of {} ({:.1}%)",
+        latest,
+        log_length,
+        percentage(latest, log_length)
+    );
+
+    if !view.lagging_plugins().is_empty() {
+        println!(
+            "plugins being rebuilt: {}",
+            view.lagging_plugins().join(", ")
+        );
+    }
+
+    println!("rows:");
+    for (table, count) in view.reader()?.table_row_counts()? {
+        println!("    {:<24}{}", table, count);
+    }
+
+    Ok(())
+}
+
+fn query(view: &FlumeViewSql, args: &Args) -> Result<(), Error> {
+    let reader = view.reader()?;
+    let name = args.positional(0, "query name")?;
+
+    match name {
+        "thread" => print_json(&reader.thread(args.positional(1, "root id")?)?),
+        "backlinks" => print_json(&reader.back_link_references(args.positional(1, "id")?, 0.0)?),
+        "friends" => print_json(&reader.friends(args.positional(1, "feed id")?)?),
+        "search" => print_json(&reader.search(
+            args.positional(1, "search query")?,
+            args.int_option("limit", DEFAULT_LIMIT)?,
+        )?),
+        "feed" => print_json(&reader.feed(
+            args.positional(1, "feed id")?,
+            args.optional_int_option("before")?,
+            args.int_option("limit", DEFAULT_LIMIT)?,
+        )?),
+        "profile" => print_json(&reader.profile(args.positional(1, "feed id")?)?),
+        "feeds" => print_json(&reader.feed_tips(None)?),
+        "forks" => print_json(&reader.forked_feeds()?),
+        "feed-integrity" => print_json(&reader.feed_integrity(args.positional(1, "feed id")?)?),
+        "repos" => print_json(&reader.repos_by_author(args.positional(1, "feed id")?)?),
+        "repo-refs" => print_json(&reader.repo_refs(args.positional(1, "repo id")?)?),
+        "open-issues" => print_json(&reader.open_issues(args.positional(1, "repo id")?)?),
+        "gatherings" => print_json(
+            &reader.upcoming_gatherings(
+                args.optional_int_option("after")?
+                    .map(|after| after as f64)
+                    .unwrap_or_else(now_millis),
+                args.int_option("limit", DEFAULT_LIMIT)?,
+            )?,
+        ),
+        "attendees" => {
+            print_json(&reader.gathering_attendees(args.positional(1, "gathering id")?)?)
+        }
+        "pubs" => print_json(&reader.ranked_pubs(args.positional(1, "feed id")?)?),
+        "my-tags" => print_json(&reader.my_tags()?),
+        "tagged" => print_json(&reader.messages_with_tag(args.positional(1, "tag id")?)?),
+        "tags" => print_json(&reader.tags_on_message(args.positional(1, "message id")?)?),
+        "blobs" => print_json(&reader.blobs_within_hops(
+            args.positional(1, "feed id")?,
+            args.int_option("hops", DEFAULT_HOPS)?,
+        )?),
+        "my-blobs" => print_json(&reader.my_blobs()?),
+        "hidden" => print_json(&reader.hidden_content()?),
+        "blob-report" => print_json(&reader.blob_report(
+            args.positional(1, "feed id")?,
+            args.int_option("hops", DEFAULT_HOPS)?,
+            &scan_blob_store(args.required_option("blobs")?)?,
+        )?),
+        "public-feed" => print_json(&reader.public_feed(
+            feed_order(args)?,
+            args.option("before"),
+            args.option("after"),
+            args.int_option("limit", DEFAULT_LIMIT)?,
+        )?),
+        "author-feed" => print_json(&reader.author_feed(
+            args.positional(1, "feed id")?,
+            feed_order(args)?,
+            args.option("before"),
+            args.option("after"),
+            args.int_option("limit", DEFAULT_LIMIT)?,
+        )?),
+        "type-feed" => print_json(&reader.type_feed(
+            args.positional(1, "type")?,
+            feed_order(args)?,
+            args.option("before"),
+            args.option("after"),
+            args.int_option("limit", DEFAULT_LIMIT)?,
+        )?),
+        "mentions" => print_json(&reader.mentions_feed(
+            args.positional(1, "feed id")?,
+            feed_order(args)?,
+            args.option("before"),
+            args.option("after"),
+            args.int_option("limit", DEFAULT_LIMIT)?,
+        )?),
+        _ => Err(format_err!("Unknown query: {}", name)),
+    }
+}
+
+fn feed_order(args: &Args) -> Result<FeedOrder, Error> {
+    args.option("order").unwrap_or("sort_time").parse()
+}
+
+fn now_millis() -> f64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    now.as_secs() as f64 * 1000.0 + f64::from(now.subsec_millis())
+}
+
+fn export_filter(args: &Args) -> Result<ExportFilter, Error> {
+    Ok(ExportFilter {
+        content_type: args.option("type").map(|value| value.to_string()),
+        author: args.option("author").map(|value| value.to_string()),
+        min_seq: args.optional_int_option("min-seq")?,
+        max_seq: args.optional_int_option("max-seq")?,
+    })
+}
+
+fn export(view: &FlumeViewSql, args: &Args) -> Result<(), Error> {
+    let table: ExportTable = args.positional(0, "table")?.parse()?;
+    let format: ExportFormat = args.option("format").unwrap_or("ndjson").parse()?;
+    let filter = export_filter(args)?;
+
+    let stdout = std::io::stdout();
+    let mut writer = BufWriter::new(stdout.lock());
+
+    let num_rows = view.reader()?.export(table, &filter, format, &mut writer)?;
+    eprintln!("exported {} rows", num_rows);
+
+    Ok(())
+}
+
+fn export_log(view: &FlumeViewSql, args: &Args) -> Result<(), Error> {
+    let log_path = args.positional(0, "log path")?;
+    let filter = export_filter(args)?;
+
+    let num_entries = view.reader()?.export_offset_log(&filter, log_path)?;
+    eprintln!("wrote {} entries to {}", num_entries, log_path);
+
+    Ok(())
+}
+
+fn check(view: &mut FlumeViewSql, args: &Args) -> Result<(), Error> {
+    if args.has_flag("--recover") {
+        return match view.check_and_recover()? {
+            Some(event) => {
+                print_json(&event)?;
+                Err(format_err!("Db failed integrity check and was recreated"))
+            }
+            None => {
+                println!("ok");
+                Ok(())
+            }
+        };
+    }
+
+    view.check_db_integrity()?;
+    println!("ok");
+    Ok(())
+}
+
+fn rebuild(view: &mut FlumeViewSql, args: &Args) -> Result<(), Error> {
+    // Make sure the log exists before deleting the db.
+    log_length(args.required_option("log")?)?;
+
+    view.rebuild()?;
+    index(view, args)
+}
+
+fn hide(view: &mut FlumeViewSql, args: &Args) -> Result<(), Error> {
+    view.hide(&HiddenContent {
+        kind: args.positional(0, "kind")?.parse()?,
+        id: args.positional(1, "id")?.to_string(),
+        purge: args.has_flag("--purge"),
+    })
+}
+
+fn unhide(view: &mut FlumeViewSql, args: &Args) -> Result<(), Error> {
+    view.unhide(
+        args.positional(0, "kind")?.parse()?,
+        args.positional(1, "id")?,
+    )
+}
+
+fn run(args: &Args) -> Result<(), Error> {
+    let mut view = args.open_view()?;
+
+    match args.command.as_str() {
+        "index" => index(&mut view, args),
+        "status" => status(&view, args),
+        "query" => query(&view, args),
+        "export" => export(&view, args),
+        "export-log" => export_log(&view, args),
+        "check" => check(&mut view, args),
+        "rebuild" => rebuild(&mut view, args),
+        "hide" => hide(&mut view, args),
+        "unhide" => unhide(&mut view, args),
+        command => Err(format_err!("Unknown command: {}", command)),
+    }
+}
+
+fn main() {
+    let raw_args: Vec<String> = std::env::args().skip(1).collect();
+
+    if raw_args.is_empty() || raw_args.iter().any(|arg| arg == "--help") {
+        println!("{}", USAGE);
+        return;
+    }
+
+    let result = Args::parse(&raw_args).and_then(|args| run(&args));
+
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+}
@@ -16,7 +16,8 @@ pub fn create_blob_links_tables(connection: &Connection) -> Result<usize, Error>
 
 pub fn create_blob_links_views(connection: &Connection) -> Result<usize, Error> {
     connection.execute(
-        "
+        &format!(
+            "
         CREATE VIEW IF NOT EXISTS blob_links AS
         SELECT 
         blob_links_raw.id as id, 
@@ -27,7 +28,10 @@ pub fn create_blob_links_views(connection: &Connection) -> Result<usize, Error>
         FROM blob_links_raw 
         JOIN keys ON keys.id=blob_links_raw.link_from_key_id
         JOIN blobs ON blobs.id=blob_links_raw.link_to_blob_id
+        WHERE NOT {}
         ",
+            hidden_message_condition("blob_links_raw.link_from_key_id")
+        ),
         NO_PARAMS,
     )
 }
@@ -36,24 +40,21 @@ pub fn insert_blob_links(
     connection: &Connection,
     links: &[&serde_json::Value],
     message_key_id: i64,
-) {
-    let mut insert_link_stmt = connection
-        .prepare_cached(
-            "INSERT INTO blob_links_raw (link_from_key_id, link_to_blob_id) VALUES (?, ?)",
-        )
-        .unwrap();
+) -> Result<(), Error> {
+    let mut insert_link_stmt = connection.prepare_cached(
+        "INSERT INTO blob_links_raw (link_from_key_id, link_to_blob_id) VALUES (?, ?)",
+    )?;
 
-    links
+    for link in links
         .iter()
-        .filter(|link| link.is_string())
-        .map(|link| link.as_str().unwrap())
+        .filter_map(|link| link.as_str())
         .filter(|link| link.starts_with('&'))
-        .map(|link| find_or_create_blob(&connection, link).unwrap())
-        .for_each(|link_id| {
-            insert_link_stmt
-                .execute(&[&message_key_id, &link_id])
-                .unwrap();
-        });
+    {
+        let link_id = find_or_create_blob(&connection, link)?;
+        insert_link_stmt.execute(&[&message_key_id, &link_id])?;
+    }
+
+    Ok(())
 }
 
 pub fn create_blob_links_indices(connection: &Connection) -> Result<usize, Error> {
@@ -0,0 +1,87 @@
+use base64::encode;
+use failure::Error;
+use rusqlite::Connection;
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use flume_view_sql::queries::blobs_within_hops;
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct BlobReport {
+    // Blobs stored locally that no message from a feed in range links to.
+    pub orphaned: Vec<String>,
+    // Blobs that messages from feeds in range link to, but aren't stored locally.
+    pub missing: Vec<String>,
+}
+
+// Compares `local_blobs` with the blobs linked to by `id` and the feeds within `hops` follows of
+// it, to find the blobs that can be deleted and the ones that should be fetched.
+pub fn blob_report(
+    connection: &Connection,
+    id: &str,
+    hops: i64,
+    local_blobs: &[String],
+) -> Result<BlobReport, Error> {
+    let referenced: HashSet<String> = blobs_within_hops(connection, id, hops)?
+        .into_iter()
+        .map(|blob| blob.blob)
+        .collect();
+    let local: HashSet<&String> = local_blobs.iter().collect();
+
+    let mut orphaned: Vec<String> = local
+        .iter()
+        .filter(|blob| !referenced.contains(blob.as_str()))
+        .map(|blob| blob.to_string())
+        .collect();
+    let mut missing: Vec<String> = referenced
+        .iter()
+        .filter(|blob| !local.contains(blob))
+        .cloned()
+        .collect();
+
+    orphaned.sort();
+    missing.sort();
+
+    Ok(BlobReport { orphaned, missing })
+}
+
+// Lists the blobs in a blob store like `~/.ssb/blobs`. They're kept in `sha256/<first two hex
+// chars of the hash>/<the rest of the hash>`.
+pub fn scan_blob_store(blobs_path: &str) -> Result<Vec<String>, Error> {
+    let mut blobs = Vec::new();
+
+    for dir in fs::read_dir(Path::new(blobs_path).join("sha256"))? {
+        let dir = dir?;
+        if !dir.file_type()?.is_dir() {
+            continue;
+        }
+        let prefix = dir.file_name().to_string_lossy().into_owned();
+
+        for file in fs::read_dir(dir.path())? {
+            let file = file?;
+            let hex = format!("{}{}", prefix, file.file_name().to_string_lossy());
+
+            match blob_id_from_hex(&hex) {
+                Some(blob) => blobs.push(blob),
+                None => warn!("Skipping unexpected file in blob store: {:?}", file.path()),
+            }
+        }
+    }
+
+    blobs.sort();
+    Ok(blobs)
+}
+
+fn blob_id_from_hex(hex: &str) -> Option<String> {
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+
+    let hash = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+
+    Some(format!("&{}.sha256", encode(&hash)))
+}
@@ -1,4 +1,6 @@
+use rusqlite::types::ToSql;
 use rusqlite::{Connection, Error, NO_PARAMS};
+use serde_json::Value;
 
 pub fn find_or_create_blob(connection: &Connection, blob: &str) -> Result<i64, Error> {
     let mut stmt = connection.prepare_cached("SELECT id FROM blobs WHERE blob=?1")?;
@@ -11,12 +13,86 @@ pub fn find_or_create_blob(connection: &Connection, blob: &str) -> Result<i64, E
     })
 }
 
+// Link objects like `{ link, type, size, name }` in mentions, or `{ link, type, width, height }`
+// for images, describe the blob they link to. The first message to describe a field of a blob
+// wins.
+pub fn insert_blob_metadata(connection: &Connection, content: &Value) -> Result<(), Error> {
+    match content {
+        Value::Object(object) => {
+            if let Some(blob) = object
+                .get("link")
+                .and_then(|link| link.as_str())
+                .filter(|link| link.starts_with('&'))
+            {
+                update_blob_metadata(connection, blob, content)?;
+            }
+            for value in object.values() {
+                insert_blob_metadata(connection, value)?;
+            }
+        }
+        Value::Array(values) => {
+            for value in values {
+                insert_blob_metadata(connection, value)?;
+            }
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+fn update_blob_metadata(connection: &Connection, blob: &str, link: &Value) -> Result<(), Error> {
+    let mime_type = link["type"].as_str();
+    let name = link["name"].as_str();
+    let size = link["size"].as_i64();
+    let width = link["width"].as_i64();
+    let height = link["height"].as_i64();
+
+    if mime_type.is_none()
+        && name.is_none()
+        && size.is_none()
+        && width.is_none()
+        && height.is_none()
+    {
+        return Ok(());
+    }
+
+    let blob_id = find_or_create_blob(connection, blob)?;
+
+    connection
+        .prepare_cached(
+            "
+            UPDATE blobs SET
+              mime_type = COALESCE(mime_type, ?),
+              name = COALESCE(name, ?),
+              size = COALESCE(size, ?),
+              width = COALESCE(width, ?),
+              height = COALESCE(height, ?)
+            WHERE id = ?
+            ",
+        )?
+        .execute(&[
+            &mime_type as &ToSql,
+            &name,
+            &size,
+            &width,
+            &height,
+            &blob_id,
+        ])?;
+
+    Ok(())
+}
+
 pub fn create_blobs_tables(connection: &Connection) -> Result<usize, Error> {
     trace!("Creating blobs tables");
     connection.execute(
         "CREATE TABLE IF NOT EXISTS blobs (
           id INTEGER PRIMARY KEY,
-          blob TEXT UNIQUE
+          blob TEXT UNIQUE,
+          mime_type TEXT,
+          name TEXT,
+          size INTEGER,
+          width INTEGER,
+          height INTEGER
         )",
         NO_PARAMS,
     )
@@ -0,0 +1,65 @@
+use rusqlite::{Connection, Error, NO_PARAMS};
+
+use flume_view_sql::*;
+
+// The feeds the local identity blocks, publicly or privately, according to `contacts_raw`.
+pub fn find_blocked_authors(connection: &Connection) -> Result<Vec<i64>, Error> {
+    let mut stmt = connection.prepare_cached(
+        "
+        SELECT DISTINCT contacts_raw.contact_author_id FROM contacts_raw
+        JOIN authors ON authors.id = contacts_raw.author_id
+        WHERE authors.is_me = 1 AND contacts_raw.state = -1
+        ORDER BY contacts_raw.contact_author_id
+        ",
+    )?;
+
+    let rows = stmt.query_map(NO_PARAMS, |row| row.get::<usize, i64>(0))?;
+    rows.collect()
+}
+
+// The feeds whose messages are currently left out of the view.
+pub fn get_blocked_authors(connection: &Connection) -> Result<Vec<i64>, Error> {
+    let mut stmt =
+        connection.prepare_cached("SELECT author_id FROM blocked_authors ORDER BY author_id")?;
+
+    let rows = stmt.query_map(NO_PARAMS, |row| row.get::<usize, i64>(0))?;
+    rows.collect()
+}
+
+pub fn is_author_blocked(connection: &Connection, message: &SsbMessage) -> Result<bool, Error> {
+    connection
+        .prepare_cached(
+            "
+            SELECT COUNT(*) FROM blocked_authors
+            JOIN authors ON authors.id = blocked_authors.author_id
+            WHERE authors.author = ?
+            ",
+        )?
+        .query_row(&[&message.value.author], |row| row.get::<usize, i64>(0) > 0)
+}
+
+pub fn set_author_blocked(
+    connection: &Connection,
+    author_id: i64,
+    is_blocked: bool,
+) -> Result<usize, Error> {
+    let sql = if is_blocked {
+        "INSERT OR IGNORE INTO blocked_authors (author_id) VALUES (?)"
+    } else {
+        "DELETE FROM blocked_authors WHERE author_id = ?"
+    };
+
+    connection.prepare_cached(sql)?.execute(&[&author_id])
+}
+
+pub fn create_blocks_tables(connection: &Connection) -> Result<usize, Error> {
+    trace!("Creating blocks tables");
+
+    // Only filled in when `FlumeViewSqlOptions::exclude_blocked_feeds` is set.
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS blocked_authors (
+          author_id INTEGER PRIMARY KEY
+        )",
+        NO_PARAMS,
+    )
+}
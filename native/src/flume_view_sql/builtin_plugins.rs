@@ -0,0 +1,548 @@
+use failure::Error;
+use rusqlite::{Connection, NO_PARAMS};
+use serde_json::Value;
+
+use flume_view_sql::*;
+
+pub fn builtin_plugins() -> Vec<Box<SqlIndexPlugin>> {
+    vec![
+        Box::new(LinksPlugin),
+        Box::new(VotesPlugin),
+        Box::new(BranchesPlugin),
+        Box::new(ContactsPlugin),
+        Box::new(AboutsPlugin),
+        Box::new(SearchPlugin),
+        Box::new(ForksPlugin),
+        Box::new(FeedsPlugin),
+        Box::new(GitPlugin),
+        Box::new(GatheringsPlugin),
+        Box::new(PubsPlugin),
+        Box::new(TagsPlugin),
+    ]
+}
+
+// Links and mentions both come from the `link` values in a message's content. Blob links come
+// from them too, but are part of the core tables.
+pub struct LinksPlugin;
+
+impl SqlIndexPlugin for LinksPlugin {
+    fn name(&self) -> &str {
+        "links"
+    }
+
+    fn version(&self) -> u32 {
+        3
+    }
+
+    fn create_schema(&self, connection: &Connection) -> Result<(), Error> {
+        create_links_tables(connection)?;
+        create_mentions_tables(connection)?;
+        create_links_indices(connection)?;
+        create_mentions_indices(connection)?;
+        create_links_views(connection)?;
+        create_mentions_views(connection)?;
+        Ok(())
+    }
+
+    fn drop_schema(&self, connection: &Connection) -> Result<(), Error> {
+        drop_tables_and_views(
+            connection,
+            &["links_raw", "mentions_raw"],
+            &["links", "mentions"],
+        )
+    }
+
+    fn on_message(
+        &self,
+        connection: &Connection,
+        message: &SsbMessage,
+        message_key_id: i64,
+    ) -> Result<(), Error> {
+        // votes are a kind of backlink, but we want to put them in their own table.
+        if is_type(message, "vote") {
+            return Ok(());
+        }
+
+        let mut links = Vec::new();
+        find_values_in_object_by_key(&message.value.content, "link", &mut links);
+        insert_links(connection, links.as_slice(), message_key_id)?;
+        insert_mentions(connection, links.as_slice(), message_key_id)?;
+        Ok(())
+    }
+
+    fn remove_feed(&self, connection: &Connection, author_id: i64) -> Result<bool, Error> {
+        delete_feed_rows(
+            connection,
+            &[
+                ("links_raw", "link_from_key_id"),
+                ("mentions_raw", "link_from_key_id"),
+            ],
+            author_id,
+        )?;
+        Ok(true)
+    }
+}
+
+pub struct VotesPlugin;
+
+impl SqlIndexPlugin for VotesPlugin {
+    fn name(&self) -> &str {
+        "votes"
+    }
+
+    fn version(&self) -> u32 {
+        1
+    }
+
+    fn create_schema(&self, connection: &Connection) -> Result<(), Error> {
+        create_votes_tables(connection)?;
+        create_votes_indices(connection)?;
+        Ok(())
+    }
+
+    fn drop_schema(&self, connection: &Connection) -> Result<(), Error> {
+        drop_tables_and_views(connection, &["votes_raw"], &[])
+    }
+
+    fn on_message(
+        &self,
+        connection: &Connection,
+        message: &SsbMessage,
+        _message_key_id: i64,
+    ) -> Result<(), Error> {
+        if is_type(message, "vote") {
+            insert_or_update_votes(connection, message)?;
+        }
+        Ok(())
+    }
+
+    fn remove_feed(&self, connection: &Connection, author_id: i64) -> Result<bool, Error> {
+        delete_author_rows(
+            connection,
+            &[("votes_raw", "link_from_author_id")],
+            author_id,
+        )?;
+        Ok(true)
+    }
+}
+
+pub struct BranchesPlugin;
+
+impl SqlIndexPlugin for BranchesPlugin {
+    fn name(&self) -> &str {
+        "branches"
+    }
+
+    fn version(&self) -> u32 {
+        1
+    }
+
+    fn create_schema(&self, connection: &Connection) -> Result<(), Error> {
+        create_branches_tables(connection)?;
+        create_branches_indices(connection)?;
+        Ok(())
+    }
+
+    fn drop_schema(&self, connection: &Connection) -> Result<(), Error> {
+        drop_tables_and_views(connection, &["branches_raw"], &[])
+    }
+
+    fn on_message(
+        &self,
+        connection: &Connection,
+        message: &SsbMessage,
+        message_key_id: i64,
+    ) -> Result<(), Error> {
+        insert_branches(connection, message, message_key_id)?;
+        Ok(())
+    }
+
+    fn remove_feed(&self, connection: &Connection, author_id: i64) -> Result<bool, Error> {
+        delete_feed_rows(
+            connection,
+            &[("branches_raw", "link_from_key_id")],
+            author_id,
+        )?;
+        Ok(true)
+    }
+}
+
+pub struct ContactsPlugin;
+
+impl SqlIndexPlugin for ContactsPlugin {
+    fn name(&self) -> &str {
+        "contacts"
+    }
+
+    fn version(&self) -> u32 {
+        1
+    }
+
+    fn create_schema(&self, connection: &Connection) -> Result<(), Error> {
+        create_contacts_tables(connection)?;
+        create_contacts_indices(connection)?;
+        Ok(())
+    }
+
+    fn drop_schema(&self, connection: &Connection) -> Result<(), Error> {
+        drop_tables_and_views(connection, &["contacts_raw"], &[])
+    }
+
+    fn on_message(
+        &self,
+        connection: &Connection,
+        message: &SsbMessage,
+        message_key_id: i64,
+    ) -> Result<(), Error> {
+        insert_or_update_contacts(connection, message, message_key_id, message.is_decrypted)?;
+        Ok(())
+    }
+
+    fn remove_feed(&self, connection: &Connection, author_id: i64) -> Result<bool, Error> {
+        delete_author_rows(connection, &[("contacts_raw", "author_id")], author_id)?;
+        Ok(true)
+    }
+}
+
+pub struct AboutsPlugin;
+
+impl SqlIndexPlugin for AboutsPlugin {
+    fn name(&self) -> &str {
+        "abouts"
+    }
+
+    fn version(&self) -> u32 {
+        1
+    }
+
+    fn create_schema(&self, connection: &Connection) -> Result<(), Error> {
+        create_abouts_tables(connection)?;
+        create_abouts_indices(connection)?;
+        create_abouts_views(connection)?;
+        Ok(())
+    }
+
+    fn drop_schema(&self, connection: &Connection) -> Result<(), Error> {
+        drop_tables_and_views(connection, &["abouts_raw"], &["abouts"])
+    }
+
+    fn on_message(
+        &self,
+        connection: &Connection,
+        message: &SsbMessage,
+        message_key_id: i64,
+    ) -> Result<(), Error> {
+        insert_abouts(connection, message, message_key_id)?;
+        Ok(())
+    }
+
+    fn remove_feed(&self, connection: &Connection, author_id: i64) -> Result<bool, Error> {
+        delete_feed_rows(connection, &[("abouts_raw", "link_from_key_id")], author_id)?;
+        Ok(true)
+    }
+}
+
+pub struct SearchPlugin;
+
+impl SqlIndexPlugin for SearchPlugin {
+    fn name(&self) -> &str {
+        "search"
+    }
+
+    fn version(&self) -> u32 {
+        1
+    }
+
+    fn create_schema(&self, connection: &Connection) -> Result<(), Error> {
+        create_search_tables(connection)?;
+        Ok(())
+    }
+
+    fn drop_schema(&self, connection: &Connection) -> Result<(), Error> {
+        drop_tables_and_views(connection, &["search_fts"], &[])
+    }
+
+    fn on_message(
+        &self,
+        connection: &Connection,
+        message: &SsbMessage,
+        message_key_id: i64,
+    ) -> Result<(), Error> {
+        insert_search_text(connection, message, message_key_id)?;
+        Ok(())
+    }
+
+    fn remove_feed(&self, connection: &Connection, author_id: i64) -> Result<bool, Error> {
+        delete_feed_rows(connection, &[("search_fts", "rowid")], author_id)?;
+        Ok(true)
+    }
+}
+
+pub struct ForksPlugin;
+
+impl SqlIndexPlugin for ForksPlugin {
+    fn name(&self) -> &str {
+        "forks"
+    }
+
+    fn version(&self) -> u32 {
+        1
+    }
+
+    fn create_schema(&self, connection: &Connection) -> Result<(), Error> {
+        create_forks_tables(connection)?;
+        create_forks_indices(connection)?;
+        Ok(())
+    }
+
+    fn drop_schema(&self, connection: &Connection) -> Result<(), Error> {
+        drop_tables_and_views(connection, &["feed_forks"], &[])
+    }
+
+    fn on_message(
+        &self,
+        connection: &Connection,
+        message: &SsbMessage,
+        message_key_id: i64,
+    ) -> Result<(), Error> {
+        insert_forks(connection, message, message_key_id).map_err(|err| err.into())
+    }
+
+    fn remove_feed(&self, connection: &Connection, author_id: i64) -> Result<bool, Error> {
+        delete_author_rows(connection, &[("feed_forks", "author_id")], author_id)?;
+        Ok(true)
+    }
+}
+
+pub struct FeedsPlugin;
+
+impl SqlIndexPlugin for FeedsPlugin {
+    fn name(&self) -> &str {
+        "feeds"
+    }
+
+    fn version(&self) -> u32 {
+        1
+    }
+
+    fn create_schema(&self, connection: &Connection) -> Result<(), Error> {
+        create_feeds_tables(connection)?;
+        create_feeds_indices(connection)?;
+        Ok(())
+    }
+
+    fn drop_schema(&self, connection: &Connection) -> Result<(), Error> {
+        drop_tables_and_views(connection, &["feeds"], &[])
+    }
+
+    fn on_message(
+        &self,
+        connection: &Connection,
+        message: &SsbMessage,
+        message_key_id: i64,
+    ) -> Result<(), Error> {
+        insert_or_update_feed(connection, message, message_key_id).map_err(|err| err.into())
+    }
+
+    fn remove_feed(&self, connection: &Connection, author_id: i64) -> Result<bool, Error> {
+        delete_author_rows(connection, &[("feeds", "author_id")], author_id)?;
+        Ok(true)
+    }
+}
+
+// git-ssb repos, their ref updates, and issues and pull requests. Packs are found through
+// `blob_links_raw`. Refs only keep the latest update and issues can be closed by other feeds, so
+// a feed can't be removed without losing what it overwrote. The plugin is rebuilt instead.
+pub struct GitPlugin;
+
+impl SqlIndexPlugin for GitPlugin {
+    fn name(&self) -> &str {
+        "git"
+    }
+
+    fn version(&self) -> u32 {
+        1
+    }
+
+    fn create_schema(&self, connection: &Connection) -> Result<(), Error> {
+        create_git_tables(connection)?;
+        create_git_indices(connection)?;
+        create_git_views(connection)?;
+        Ok(())
+    }
+
+    fn drop_schema(&self, connection: &Connection) -> Result<(), Error> {
+        drop_tables_and_views(
+            connection,
+            &["git_repos", "git_updates", "git_refs", "git_issues"],
+            &["git_packs"],
+        )
+    }
+
+    fn on_message(
+        &self,
+        connection: &Connection,
+        message: &SsbMessage,
+        message_key_id: i64,
+    ) -> Result<(), Error> {
+        insert_git(connection, message, message_key_id).map_err(|err| err.into())
+    }
+}
+
+// Anyone can update a gathering's details and only the latest are kept, so a feed can't be removed
+// without losing what it overwrote. The plugin is rebuilt instead.
+pub struct GatheringsPlugin;
+
+impl SqlIndexPlugin for GatheringsPlugin {
+    fn name(&self) -> &str {
+        "gatherings"
+    }
+
+    fn version(&self) -> u32 {
+        1
+    }
+
+    fn create_schema(&self, connection: &Connection) -> Result<(), Error> {
+        create_gatherings_tables(connection)?;
+        create_gatherings_indices(connection)?;
+        Ok(())
+    }
+
+    fn drop_schema(&self, connection: &Connection) -> Result<(), Error> {
+        drop_tables_and_views(connection, &["gatherings", "gathering_attendees"], &[])
+    }
+
+    fn on_message(
+        &self,
+        connection: &Connection,
+        message: &SsbMessage,
+        message_key_id: i64,
+    ) -> Result<(), Error> {
+        insert_gatherings(connection, message, message_key_id).map_err(|err| err.into())
+    }
+}
+
+pub struct PubsPlugin;
+
+impl SqlIndexPlugin for PubsPlugin {
+    fn name(&self) -> &str {
+        "pubs"
+    }
+
+    fn version(&self) -> u32 {
+        1
+    }
+
+    fn create_schema(&self, connection: &Connection) -> Result<(), Error> {
+        create_pubs_tables(connection)?;
+        create_pubs_indices(connection)?;
+        Ok(())
+    }
+
+    fn drop_schema(&self, connection: &Connection) -> Result<(), Error> {
+        drop_tables_and_views(connection, &["pubs"], &[])
+    }
+
+    fn on_message(
+        &self,
+        connection: &Connection,
+        message: &SsbMessage,
+        _message_key_id: i64,
+    ) -> Result<(), Error> {
+        insert_pubs(connection, message).map_err(|err| err.into())
+    }
+
+    fn remove_feed(&self, connection: &Connection, author_id: i64) -> Result<bool, Error> {
+        delete_author_rows(connection, &[("pubs", "author_id")], author_id)?;
+        Ok(true)
+    }
+}
+
+// Other feeds tag messages with the tags a feed created, so the plugin is rebuilt when a feed is
+// removed rather than leaving them pointing at nothing.
+pub struct TagsPlugin;
+
+impl SqlIndexPlugin for TagsPlugin {
+    fn name(&self) -> &str {
+        "tags"
+    }
+
+    fn version(&self) -> u32 {
+        1
+    }
+
+    fn create_schema(&self, connection: &Connection) -> Result<(), Error> {
+        create_tags_tables(connection)?;
+        create_tags_indices(connection)?;
+        Ok(())
+    }
+
+    fn drop_schema(&self, connection: &Connection) -> Result<(), Error> {
+        drop_tables_and_views(connection, &["tags", "tagged_messages"], &[])
+    }
+
+    fn on_message(
+        &self,
+        connection: &Connection,
+        message: &SsbMessage,
+        message_key_id: i64,
+    ) -> Result<(), Error> {
+        insert_tags(connection, message, message_key_id).map_err(|err| err.into())
+    }
+}
+
+fn drop_tables_and_views(
+    connection: &Connection,
+    tables: &[&str],
+    views: &[&str],
+) -> Result<(), Error> {
+    for view in views {
+        connection.execute(&format!("DROP VIEW IF EXISTS {}", view), NO_PARAMS)?;
+    }
+    // Dropping a table drops its indices too.
+    for table in tables {
+        connection.execute(&format!("DROP TABLE IF EXISTS {}", table), NO_PARAMS)?;
+    }
+    Ok(())
+}
+
+// Deletes the rows of each table that were indexed from messages by `author_id`. Tables are given
+// with the column that holds the id of the message's key.
+fn delete_feed_rows(
+    connection: &Connection,
+    tables: &[(&str, &str)],
+    author_id: i64,
+) -> Result<(), Error> {
+    for &(table, key_id_column) in tables {
+        connection.execute(
+            &format!(
+                "DELETE FROM {} WHERE {} IN (SELECT key_id FROM messages_raw WHERE author_id = ?)",
+                table, key_id_column
+            ),
+            &[&author_id],
+        )?;
+    }
+    Ok(())
+}
+
+// Deletes the rows of each table where the given column is `author_id`.
+fn delete_author_rows(
+    connection: &Connection,
+    tables: &[(&str, &str)],
+    author_id: i64,
+) -> Result<(), Error> {
+    for &(table, author_id_column) in tables {
+        connection.execute(
+            &format!("DELETE FROM {} WHERE {} = ?", table, author_id_column),
+            &[&author_id],
+        )?;
+    }
+    Ok(())
+}
+
+fn is_type(message: &SsbMessage, content_type: &str) -> bool {
+    match &message.value.content["type"] {
+        Value::String(type_string) => type_string == content_type,
+        _ => false,
+    }
+}
@@ -23,7 +23,7 @@ pub fn insert_or_update_contacts(
     message: &SsbMessage,
     _message_key_id: i64,
     is_decrypted: bool,
-) {
+) -> Result<(), Error> {
     if let Value::String(contact) = &message.value.content["contact"] {
         //Ok what should this do:
         //  - if the record already exists
@@ -43,10 +43,10 @@ pub fn insert_or_update_contacts(
             0
         };
 
-        let author_id = find_or_create_author(&connection, &message.value.author).unwrap();
-        let contact_author_id = find_or_create_author(&connection, contact).unwrap();
+        let author_id = find_or_create_author(&connection, &message.value.author)?;
+        let contact_author_id = find_or_create_author(&connection, contact)?;
 
-        let mut stmt = connection.prepare_cached("SELECT id FROM contacts_raw WHERE author_id = ? AND contact_author_id = ? AND is_decrypted = ?").unwrap();
+        let mut stmt = connection.prepare_cached("SELECT id FROM contacts_raw WHERE author_id = ? AND contact_author_id = ? AND is_decrypted = ?")?;
 
         stmt.query_row(&[&author_id, &contact_author_id, &is_decrypted as &ToSql], |row| row.get(0))
             .and_then(|id: i64|{
@@ -61,10 +61,10 @@ pub fn insert_or_update_contacts(
                     .prepare_cached("INSERT INTO contacts_raw (author_id, contact_author_id, is_decrypted, state) VALUES (?, ?, ?, ?)")
                     .map(|mut stmt| stmt.execute(&[&author_id, &contact_author_id, &is_decrypted as &ToSql, &state]))
             })
-            .unwrap()
-            .unwrap();
+            ??;
     }
 
+    Ok(())
 }
 
 pub fn create_contacts_indices(connection: &Connection) -> Result<usize, Error> {
@@ -0,0 +1,411 @@
+use failure::Error;
+use flumedb::flume_log::FlumeLog;
+use flumedb::offset_log::OffsetLog;
+use rusqlite::types::{ToSql, Value as SqlValue};
+use rusqlite::{Connection, Row};
+use serde_json::{Map, Number, Value};
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+use flume_view_sql::*;
+
+#[derive(Debug, Fail)]
+pub enum ExportError {
+    #[fail(display = "Unknown export table: {}", name)]
+    UnknownTable { name: String },
+    #[fail(display = "Unknown export format: {}", name)]
+    UnknownFormat { name: String },
+    #[fail(display = "The {} table can't be filtered by {}", table, filter)]
+    UnsupportedFilter {
+        table: &'static str,
+        filter: &'static str,
+    },
+    #[fail(display = "Log file already exists: {}", path)]
+    LogAlreadyExists { path: String },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Ndjson,
+    Csv,
+}
+
+impl FromStr for ExportFormat {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<ExportFormat, Error> {
+        match name {
+            "ndjson" => Ok(ExportFormat::Ndjson),
+            "csv" => Ok(ExportFormat::Csv),
+            _ => Err(ExportError::UnknownFormat {
+                name: name.to_string(),
+            }
+            .into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportTable {
+    Messages,
+    Links,
+    Contacts,
+    Votes,
+    Abouts,
+}
+
+impl FromStr for ExportTable {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<ExportTable, Error> {
+        match name {
+            "messages" => Ok(ExportTable::Messages),
+            "links" => Ok(ExportTable::Links),
+            "contacts_raw" | "contacts" => Ok(ExportTable::Contacts),
+            "votes_raw" | "votes" => Ok(ExportTable::Votes),
+            "abouts" => Ok(ExportTable::Abouts),
+            _ => Err(ExportError::UnknownTable {
+                name: name.to_string(),
+            }
+            .into()),
+        }
+    }
+}
+
+impl ExportTable {
+    fn name(self) -> &'static str {
+        match self {
+            ExportTable::Messages => "messages",
+            ExportTable::Links => "links",
+            ExportTable::Contacts => "contacts_raw",
+            ExportTable::Votes => "votes_raw",
+            ExportTable::Abouts => "abouts",
+        }
+    }
+
+    // Each query has the message the row came from joined as `messages_raw` so it can be filtered,
+    // except for contacts and votes which only keep the latest state.
+    fn select_sql(self) -> &'static str {
+        match self {
+            ExportTable::Messages => {
+                "
+                SELECT
+                    messages_raw.flume_seq,
+                    keys.key,
+                    authors.author,
+                    messages_raw.seq,
+                    messages_raw.received_time,
+                    messages_raw.asserted_time,
+                    messages_raw.content_type,
+                    root_keys.key AS root,
+                    fork_keys.key AS fork,
+                    messages_raw.is_decrypted,
+                    messages_raw.content
+                FROM messages_raw
+                JOIN keys ON keys.id = messages_raw.key_id
+                JOIN authors ON authors.id = messages_raw.author_id
+                LEFT JOIN keys AS root_keys ON root_keys.id = messages_raw.root_id
+                LEFT JOIN keys AS fork_keys ON fork_keys.id = messages_raw.fork_id
+                "
+            }
+            ExportTable::Links => {
+                "
+                SELECT
+                    messages_raw.flume_seq,
+                    keys_from.key AS link_from_key,
+                    keys_to.key AS link_to_key,
+                    authors.author,
+                    messages_raw.content_type
+                FROM links_raw
+                JOIN messages_raw ON messages_raw.key_id = links_raw.link_from_key_id
+                JOIN keys AS keys_from ON keys_from.id = links_raw.link_from_key_id
+                JOIN keys AS keys_to ON keys_to.id = links_raw.link_to_key_id
+                JOIN authors ON authors.id = messages_raw.author_id
+                "
+            }
+            ExportTable::Contacts => {
+                "
+                SELECT
+                    authors.author,
+                    contact_authors.author AS contact,
+                    contacts_raw.state,
+                    contacts_raw.is_decrypted
+                FROM contacts_raw
+                JOIN authors ON authors.id = contacts_raw.author_id
+                JOIN authors AS contact_authors ON contact_authors.id = contacts_raw.contact_author_id
+                "
+            }
+            ExportTable::Votes => {
+                "
+                SELECT
+                    authors.author,
+                    keys.key AS link_to_key
+                FROM votes_raw
+                JOIN authors ON authors.id = votes_raw.link_from_author_id
+                JOIN keys ON keys.id = votes_raw.link_to_key_id
+                "
+            }
+            ExportTable::Abouts => {
+                "
+                SELECT
+                    messages_raw.flume_seq,
+                    keys_from.key AS link_from_key,
+                    keys_to.key AS link_to_key,
+                    authors_to.author AS link_to_author,
+                    authors.author,
+                    messages_raw.content
+                FROM abouts_raw
+                JOIN messages_raw ON messages_raw.key_id = abouts_raw.link_from_key_id
+                JOIN keys AS keys_from ON keys_from.id = abouts_raw.link_from_key_id
+                LEFT JOIN keys AS keys_to ON keys_to.id = abouts_raw.link_to_key_id
+                LEFT JOIN authors AS authors_to ON authors_to.id = abouts_raw.link_to_author_id
+                JOIN authors ON authors.id = messages_raw.author_id
+                "
+            }
+        }
+    }
+
+    fn author_column(self) -> &'static str {
+        match self {
+            ExportTable::Contacts => "contacts_raw.author_id",
+            ExportTable::Votes => "votes_raw.link_from_author_id",
+            _ => "messages_raw.author_id",
+        }
+    }
+
+    fn has_message(self) -> bool {
+        match self {
+            ExportTable::Contacts | ExportTable::Votes => false,
+            _ => true,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ExportFilter {
+    pub content_type: Option<String>,
+    pub author: Option<String>,
+    // Inclusive bounds on the flume_seq of the message each row came from.
+    pub min_seq: Option<i64>,
+    pub max_seq: Option<i64>,
+}
+
+// Writes every row of `table` that matches `filter` to `writer`, one row at a time. Returns the
+// number of rows written.
+pub fn export_table<W: Write>(
+    connection: &Connection,
+    table: ExportTable,
+    filter: &ExportFilter,
+    format: ExportFormat,
+    writer: &mut W,
+) -> Result<usize, Error> {
+    let mut sql = table.select_sql().to_string();
+    let params = push_filter_conditions(&mut sql, table, filter)?;
+    if table.has_message() {
+        sql.push_str(" ORDER BY messages_raw.flume_seq ASC");
+    }
+
+    let mut stmt = connection.prepare(&sql)?;
+    let column_names: Vec<String> = stmt
+        .column_names()
+        .iter()
+        .map(|name| name.to_string())
+        .collect();
+
+    if format == ExportFormat::Csv {
+        write_csv_record(writer, column_names.iter().map(|name| name.as_str()))?;
+    }
+
+    let rows = stmt.query_and_then(&params, |row| write_row(writer, row, &column_names, format))?;
+
+    let mut num_rows = 0;
+    for row in rows {
+        row?;
+        num_rows += 1;
+    }
+
+    writer.flush()?;
+
+    Ok(num_rows)
+}
+
+// Writes the original log entries of the messages that match `filter` to a new flumelog-offset
+// file at `log_path`. Entries get new offsets, so flume_seqs in the new log won't match the view.
+pub fn export_offset_log(
+    connection: &Connection,
+    filter: &ExportFilter,
+    log_path: &str,
+) -> Result<usize, Error> {
+    if Path::new(log_path).exists() {
+        return Err(ExportError::LogAlreadyExists {
+            path: log_path.to_string(),
+        }
+        .into());
+    }
+
+    let mut log = OffsetLog::<u32>::new(log_path)?;
+
+    let mut sql = "SELECT messages_raw.raw FROM messages_raw".to_string();
+    let params = push_filter_conditions(&mut sql, ExportTable::Messages, filter)?;
+    sql.push_str(" ORDER BY messages_raw.flume_seq ASC");
+
+    let mut stmt = connection.prepare(&sql)?;
+    // Purged messages have no raw entry to write.
+    let rows = stmt.query_and_then(&params, |row| -> Result<bool, Error> {
+        let raw: Option<Vec<u8>> = row.get_checked(0)?;
+        match raw {
+            Some(raw) => log.append(&raw).map(|_| true).map_err(|err| err.into()),
+            None => Ok(false),
+        }
+    })?;
+
+    let mut num_entries = 0;
+    for row in rows {
+        if row? {
+            num_entries += 1;
+        }
+    }
+
+    Ok(num_entries)
+}
+
+// Appends a WHERE clause for `filter` to `sql`, leaving out hidden rows, and returns the params it
+// needs.
+fn push_filter_conditions<'a>(
+    sql: &mut String,
+    table: ExportTable,
+    filter: &'a ExportFilter,
+) -> Result<Vec<&'a ToSql>, Error> {
+    let mut params: Vec<&ToSql> = Vec::new();
+
+    // Hidden content and blocked feeds are left out, the same as in the views.
+    let mut conditions = vec![if table.has_message() {
+        format!("NOT {}", hidden_message_condition("messages_raw.key_id"))
+    } else {
+        format!("NOT {}", hidden_author_condition(table.author_column()))
+    }];
+
+    if let Some(ref author) = filter.author {
+        conditions.push(format!(
+            "{} = (SELECT id FROM authors WHERE author = ?)",
+            table.author_column()
+        ));
+        params.push(author);
+    }
+
+    if !table.has_message() {
+        if filter.content_type.is_some() {
+            return Err(unsupported_filter(table, "content type"));
+        }
+        if filter.min_seq.is_some() || filter.max_seq.is_some() {
+            return Err(unsupported_filter(table, "flume_seq"));
+        }
+    }
+
+    if let Some(ref content_type) = filter.content_type {
+        conditions.push("messages_raw.content_type = ?".to_string());
+        params.push(content_type);
+    }
+    if let Some(ref min_seq) = filter.min_seq {
+        conditions.push("messages_raw.flume_seq >= ?".to_string());
+        params.push(min_seq);
+    }
+    if let Some(ref max_seq) = filter.max_seq {
+        conditions.push("messages_raw.flume_seq <= ?".to_string());
+        params.push(max_seq);
+    }
+
+    sql.push_str(" WHERE ");
+    sql.push_str(&conditions.join(" AND "));
+
+    Ok(params)
+}
+
+fn unsupported_filter(table: ExportTable, filter: &'static str) -> Error {
+    ExportError::UnsupportedFilter {
+        table: table.name(),
+        filter,
+    }
+    .into()
+}
+
+fn write_row<W: Write>(
+    writer: &mut W,
+    row: &Row,
+    column_names: &[String],
+    format: ExportFormat,
+) -> Result<(), Error> {
+    let mut values = Vec::with_capacity(column_names.len());
+    for index in 0..column_names.len() {
+        values.push(row.get_checked::<usize, SqlValue>(index)?);
+    }
+
+    match format {
+        ExportFormat::Ndjson => {
+            let mut object = Map::new();
+            for (name, value) in column_names.iter().zip(values) {
+                object.insert(name.clone(), json_value(name, value));
+            }
+            serde_json::to_writer(&mut *writer, &object)?;
+            writer.write_all(b"\n")?;
+        }
+        ExportFormat::Csv => {
+            let fields: Vec<String> = values.into_iter().map(csv_field).collect();
+            write_csv_record(writer, fields.iter().map(|field| field.as_str()))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn json_value(column_name: &str, value: SqlValue) -> Value {
+    match value {
+        SqlValue::Null => Value::Null,
+        SqlValue::Integer(integer) => Value::from(integer),
+        SqlValue::Real(real) => Number::from_f64(real)
+            .map(Value::Number)
+            .unwrap_or(Value::Null),
+        // Content is stored as json so nest it rather than exporting it as a string.
+        SqlValue::Text(text) => {
+            if column_name == "content" {
+                serde_json::from_str(&text).unwrap_or(Value::String(text))
+            } else {
+                Value::String(text)
+            }
+        }
+        SqlValue::Blob(bytes) => Value::String(base64::encode(&bytes)),
+    }
+}
+
+fn csv_field(value: SqlValue) -> String {
+    match value {
+        SqlValue::Null => String::new(),
+        SqlValue::Integer(integer) => integer.to_string(),
+        SqlValue::Real(real) => real.to_string(),
+        SqlValue::Text(text) => text,
+        SqlValue::Blob(bytes) => base64::encode(&bytes),
+    }
+}
+
+fn write_csv_record<'a, W, I>(writer: &mut W, fields: I) -> Result<(), Error>
+where
+    W: Write,
+    I: Iterator<Item = &'a str>,
+{
+    let record = fields
+        .map(|field| {
+            if field.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.to_string()
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(",");
+
+    writer.write_all(record.as_bytes())?;
+    writer.write_all(b"\r\n")?;
+
+    Ok(())
+}
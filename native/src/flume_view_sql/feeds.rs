@@ -0,0 +1,85 @@
+use flume_view_sql::*;
+use rusqlite::types::ToSql;
+use rusqlite::{Connection, Error, NO_PARAMS};
+
+// Updates the tip, message count and first and last seen times of the author of `message`.
+pub fn insert_or_update_feed(
+    connection: &Connection,
+    message: &SsbMessage,
+    message_key_id: i64,
+) -> Result<(), Error> {
+    let author_id = find_or_create_author(&connection, &message.value.author)?;
+
+    let mut select_stmt =
+        connection.prepare_cached("SELECT latest_seq FROM feeds WHERE author_id = ?")?;
+
+    match select_stmt.query_row(&[&author_id], |row| row.get::<usize, i64>(0)) {
+        Ok(latest_seq) => {
+            // Messages don't always arrive in order so only move the tip forwards.
+            let (latest_seq, latest_key_id) = if i64::from(message.value.sequence) >= latest_seq {
+                (i64::from(message.value.sequence), Some(message_key_id))
+            } else {
+                (latest_seq, None)
+            };
+
+            connection
+                .prepare_cached(
+                    "UPDATE feeds SET
+                      latest_seq = ?,
+                      latest_key_id = COALESCE(?, latest_key_id),
+                      message_count = message_count + 1,
+                      first_seen = MIN(first_seen, ?),
+                      last_seen = MAX(last_seen, ?)
+                    WHERE author_id = ?",
+                )?
+                .execute(&[
+                    &latest_seq as &ToSql,
+                    &latest_key_id as &ToSql,
+                    &message.timestamp,
+                    &message.timestamp,
+                    &author_id,
+                ])?;
+        }
+        Err(Error::QueryReturnedNoRows) => {
+            connection
+                .prepare_cached(
+                    "INSERT INTO feeds (author_id, latest_seq, latest_key_id, message_count, first_seen, last_seen) VALUES (?, ?, ?, 1, ?, ?)",
+                )?
+                .execute(&[
+                    &author_id as &ToSql,
+                    &message.value.sequence,
+                    &message_key_id,
+                    &message.timestamp,
+                    &message.timestamp,
+                ])?;
+        }
+        Err(err) => return Err(err),
+    }
+
+    Ok(())
+}
+
+pub fn create_feeds_tables(connection: &Connection) -> Result<usize, Error> {
+    trace!("Creating feeds tables");
+
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS feeds (
+          author_id INTEGER PRIMARY KEY,
+          latest_seq INTEGER,
+          latest_key_id INTEGER,
+          message_count INTEGER,
+          first_seen REAL,
+          last_seen REAL
+        )",
+        NO_PARAMS,
+    )
+}
+
+pub fn create_feeds_indices(connection: &Connection) -> Result<usize, Error> {
+    trace!("Creating feeds indices");
+
+    connection.execute(
+        "CREATE INDEX IF NOT EXISTS feeds_last_seen_index on feeds (last_seen)",
+        NO_PARAMS,
+    )
+}
@@ -0,0 +1,67 @@
+use flume_view_sql::*;
+use rusqlite::types::ToSql;
+use rusqlite::{Connection, Error, NO_PARAMS};
+
+// Records a fork for each message already in the view with the same author and sequence as
+// `message`. Call this before inserting `message` into `messages_raw`.
+pub fn insert_forks(
+    connection: &Connection,
+    message: &SsbMessage,
+    message_key_id: i64,
+) -> Result<(), Error> {
+    let author_id = find_or_create_author(&connection, &message.value.author)?;
+
+    let mut select_stmt = connection.prepare_cached(
+        "SELECT key_id FROM messages_raw WHERE author_id = ? AND seq = ? AND key_id != ?",
+    )?;
+
+    let conflicting_key_ids = select_stmt
+        .query_map(
+            &[
+                &author_id as &ToSql,
+                &message.value.sequence,
+                &message_key_id,
+            ],
+            |row| row.get::<usize, i64>(0),
+        )?
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let mut insert_stmt = connection.prepare_cached(
+        "INSERT INTO feed_forks (author_id, seq, key_id, conflicting_key_id) VALUES (?, ?, ?, ?)",
+    )?;
+
+    for conflicting_key_id in conflicting_key_ids {
+        insert_stmt.execute(&[
+            &author_id as &ToSql,
+            &message.value.sequence,
+            &conflicting_key_id,
+            &message_key_id,
+        ])?;
+    }
+
+    Ok(())
+}
+
+pub fn create_forks_tables(connection: &Connection) -> Result<usize, Error> {
+    trace!("Creating forks tables");
+
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS feed_forks (
+          id INTEGER PRIMARY KEY,
+          author_id INTEGER,
+          seq INTEGER,
+          key_id INTEGER,
+          conflicting_key_id INTEGER
+        )",
+        NO_PARAMS,
+    )
+}
+
+pub fn create_forks_indices(connection: &Connection) -> Result<usize, Error> {
+    trace!("Creating forks indices");
+
+    connection.execute(
+        "CREATE INDEX IF NOT EXISTS feed_forks_author_id_index on feed_forks (author_id)",
+        NO_PARAMS,
+    )
+}
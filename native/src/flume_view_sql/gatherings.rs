@@ -0,0 +1,183 @@
+use flume_view_sql::*;
+use rusqlite::types::ToSql;
+use rusqlite::{Connection, Error, NO_PARAMS};
+
+// Indexes `gathering` messages and the `about` messages that describe and attend them.
+pub fn insert_gatherings(
+    connection: &Connection,
+    message: &SsbMessage,
+    message_key_id: i64,
+) -> Result<(), Error> {
+    let content = &message.value.content;
+
+    match content["type"].as_str() {
+        Some("gathering") => {
+            let author_id = find_or_create_author(connection, &message.value.author)?;
+
+            connection
+                .prepare_cached(
+                    "INSERT INTO gatherings (key_id, author_id, asserted_time) VALUES (?, ?, ?)",
+                )?
+                .execute(&[
+                    &message_key_id as &ToSql,
+                    &author_id,
+                    &message.value.timestamp,
+                ])?;
+            Ok(())
+        }
+        Some("about") => match content["about"].as_str() {
+            Some(gathering) if gathering.starts_with('%') => {
+                update_gathering(connection, message, gathering)?;
+                update_attendee(connection, message, gathering)
+            }
+            _ => Ok(()),
+        },
+        _ => Ok(()),
+    }
+}
+
+// Only the gathering's author can change its details. Their abouts are in the same feed as the
+// gathering, so they're indexed after it and in order, and the latest value of each field wins.
+fn update_gathering(
+    connection: &Connection,
+    message: &SsbMessage,
+    gathering: &str,
+) -> Result<(), Error> {
+    let content = &message.value.content;
+
+    let start_time = match &content["startDateTime"] {
+        Value::Object(start) => start["epoch"].as_f64(),
+        start => start.as_f64(),
+    };
+    let image = match &content["image"] {
+        Value::Object(image) => image["link"].as_str(),
+        image => image.as_str(),
+    };
+
+    let title = content["title"].as_str();
+    let description = content["description"].as_str();
+    let location = content["location"].as_str();
+
+    if title.is_none()
+        && description.is_none()
+        && location.is_none()
+        && start_time.is_none()
+        && image.is_none()
+    {
+        return Ok(());
+    }
+
+    let author_id = find_or_create_author(connection, &message.value.author)?;
+
+    connection
+        .prepare_cached(
+            "
+            UPDATE gatherings SET
+              title = COALESCE(?, title),
+              description = COALESCE(?, description),
+              location = COALESCE(?, location),
+              start_time = COALESCE(?, start_time),
+              image = COALESCE(?, image)
+            WHERE key_id = (SELECT id FROM keys WHERE key = ?)
+            AND author_id = ?
+            ",
+        )?
+        .execute(&[
+            &title as &ToSql,
+            &description,
+            &location,
+            &start_time,
+            &image,
+            &gathering,
+            &author_id,
+        ])?;
+
+    Ok(())
+}
+
+// `attendee` is `{ link }` to attend and `{ link, remove: true }` to stop attending. People can
+// only change their own attendance.
+fn update_attendee(
+    connection: &Connection,
+    message: &SsbMessage,
+    gathering: &str,
+) -> Result<(), Error> {
+    let attendee = &message.value.content["attendee"];
+
+    if attendee["link"].as_str() != Some(message.value.author.as_str()) {
+        return Ok(());
+    }
+
+    let is_attending = attendee["remove"].as_bool() != Some(true);
+    let gathering_key_id = find_or_create_key(connection, gathering)?;
+    let author_id = find_or_create_author(connection, &message.value.author)?;
+
+    connection
+        .prepare_cached("INSERT OR IGNORE INTO gathering_attendees (gathering_key_id, author_id, is_attending, asserted_time) VALUES (?, ?, ?, ?)")?
+        .execute(&[
+            &gathering_key_id as &ToSql,
+            &author_id,
+            &is_attending,
+            &message.value.timestamp,
+        ])?;
+
+    connection
+        .prepare_cached(
+            "
+            UPDATE gathering_attendees SET is_attending = ?, asserted_time = ?
+            WHERE gathering_key_id = ? AND author_id = ? AND asserted_time <= ?
+            ",
+        )?
+        .execute(&[
+            &is_attending as &ToSql,
+            &message.value.timestamp,
+            &gathering_key_id,
+            &author_id,
+            &message.value.timestamp,
+        ])?;
+
+    Ok(())
+}
+
+pub fn create_gatherings_tables(connection: &Connection) -> Result<usize, Error> {
+    trace!("Creating gatherings tables");
+
+    // `start_time` is in milliseconds since the epoch, like the message timestamps.
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS gatherings (
+          key_id INTEGER PRIMARY KEY,
+          author_id INTEGER,
+          title TEXT,
+          description TEXT,
+          location TEXT,
+          start_time REAL,
+          image TEXT,
+          asserted_time REAL
+        )",
+        NO_PARAMS,
+    )?;
+
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS gathering_attendees (
+          id INTEGER PRIMARY KEY,
+          gathering_key_id INTEGER,
+          author_id INTEGER,
+          is_attending BOOLEAN,
+          asserted_time REAL
+        )",
+        NO_PARAMS,
+    )
+}
+
+pub fn create_gatherings_indices(connection: &Connection) -> Result<usize, Error> {
+    trace!("Creating gatherings indices");
+
+    connection.execute(
+        "CREATE INDEX IF NOT EXISTS gatherings_start_time_index on gatherings (start_time)",
+        NO_PARAMS,
+    )?;
+    connection.execute(
+        "CREATE UNIQUE INDEX IF NOT EXISTS gathering_attendees_gathering_author_index on gathering_attendees (gathering_key_id, author_id)",
+        NO_PARAMS,
+    )
+}
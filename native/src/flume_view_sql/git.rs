@@ -0,0 +1,327 @@
+use flume_view_sql::*;
+use rusqlite::types::ToSql;
+use rusqlite::{Connection, Error, NO_PARAMS};
+
+// Indexes the git-ssb message types: `git-repo`, `git-update`, `issue`, `pull-request` and
+// `issue-edit`, plus `about` messages that rename a repo.
+pub fn insert_git(
+    connection: &Connection,
+    message: &SsbMessage,
+    message_key_id: i64,
+) -> Result<(), Error> {
+    let content = &message.value.content;
+
+    match content["type"].as_str() {
+        Some("git-repo") => insert_repo(connection, message, message_key_id),
+        Some("git-update") => insert_update(connection, message, message_key_id),
+        Some("issue") => insert_issue(connection, message, message_key_id, "issue"),
+        Some("pull-request") => insert_issue(connection, message, message_key_id, "pull-request"),
+        Some("issue-edit") => update_issues(connection, message),
+        Some("about") => rename_repo(connection, message),
+        _ => Ok(()),
+    }
+}
+
+fn insert_repo(
+    connection: &Connection,
+    message: &SsbMessage,
+    message_key_id: i64,
+) -> Result<(), Error> {
+    let content = &message.value.content;
+    let author_id = find_or_create_author(connection, &message.value.author)?;
+
+    let upstream_key_id = match content["upstream"].as_str() {
+        Some(upstream) => Some(find_or_create_key(connection, upstream)?),
+        None => None,
+    };
+
+    connection
+        .prepare_cached("INSERT INTO git_repos (key_id, author_id, name, upstream_key_id, asserted_time) VALUES (?, ?, ?, ?, ?)")?
+        .execute(&[
+            &message_key_id as &ToSql,
+            &author_id,
+            &content["name"].as_str(),
+            &upstream_key_id,
+            &message.value.timestamp,
+        ])?;
+
+    Ok(())
+}
+
+fn insert_update(
+    connection: &Connection,
+    message: &SsbMessage,
+    message_key_id: i64,
+) -> Result<(), Error> {
+    let content = &message.value.content;
+
+    let repo = match content["repo"].as_str() {
+        Some(repo) => repo,
+        None => return Ok(()),
+    };
+
+    let repo_key_id = find_or_create_key(connection, repo)?;
+    let author_id = find_or_create_author(connection, &message.value.author)?;
+
+    connection
+        .prepare_cached("INSERT INTO git_updates (key_id, repo_key_id, author_id, asserted_time) VALUES (?, ?, ?, ?)")?
+        .execute(&[
+            &message_key_id as &ToSql,
+            &repo_key_id,
+            &author_id,
+            &message.value.timestamp,
+        ])?;
+
+    if let Some(refs) = content["refs"].as_object() {
+        for (ref_name, sha) in refs {
+            update_ref(
+                connection,
+                repo_key_id,
+                ref_name,
+                sha.as_str(),
+                message_key_id,
+                message.value.timestamp,
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+// Keeps the latest value of each ref. A null sha means the ref was deleted.
+fn update_ref(
+    connection: &Connection,
+    repo_key_id: i64,
+    ref_name: &str,
+    sha: Option<&str>,
+    update_key_id: i64,
+    asserted_time: f64,
+) -> Result<(), Error> {
+    let mut select_stmt = connection.prepare_cached(
+        "SELECT id, asserted_time FROM git_refs WHERE repo_key_id = ? AND ref = ?",
+    )?;
+
+    match select_stmt.query_row(&[&repo_key_id as &ToSql, &ref_name], |row| {
+        (row.get::<usize, i64>(0), row.get::<usize, f64>(1))
+    }) {
+        // Updates don't always arrive in order, so keep whichever is newest.
+        Ok((_, latest_time)) if latest_time > asserted_time => Ok(()),
+        Ok((id, _)) => match sha {
+            Some(sha) => connection
+                .prepare_cached(
+                    "UPDATE git_refs SET sha = ?, update_key_id = ?, asserted_time = ? WHERE id = ?",
+                )?
+                .execute(&[&sha as &ToSql, &update_key_id, &asserted_time, &id])
+                .map(|_| ()),
+            None => connection
+                .prepare_cached("DELETE FROM git_refs WHERE id = ?")?
+                .execute(&[&id])
+                .map(|_| ()),
+        },
+        Err(Error::QueryReturnedNoRows) => match sha {
+            Some(sha) => connection
+                .prepare_cached("INSERT INTO git_refs (repo_key_id, ref, sha, update_key_id, asserted_time) VALUES (?, ?, ?, ?, ?)")?
+                .execute(&[
+                    &repo_key_id as &ToSql,
+                    &ref_name,
+                    &sha,
+                    &update_key_id,
+                    &asserted_time,
+                ])
+                .map(|_| ()),
+            None => Ok(()),
+        },
+        Err(err) => Err(err),
+    }
+}
+
+fn insert_issue(
+    connection: &Connection,
+    message: &SsbMessage,
+    message_key_id: i64,
+    kind: &str,
+) -> Result<(), Error> {
+    let content = &message.value.content;
+
+    let repo = match content["project"].as_str() {
+        Some(repo) => repo,
+        None => return Ok(()),
+    };
+
+    let repo_key_id = find_or_create_key(connection, repo)?;
+    let author_id = find_or_create_author(connection, &message.value.author)?;
+
+    // Issues without a title use the first line of their text.
+    let title = content["title"].as_str().or_else(|| {
+        content["text"]
+            .as_str()
+            .and_then(|text| text.lines().next())
+    });
+
+    connection
+        .prepare_cached("INSERT INTO git_issues (key_id, kind, repo_key_id, author_id, title, is_open, asserted_time, updated_time) VALUES (?, ?, ?, ?, ?, 1, ?, ?)")?
+        .execute(&[
+            &message_key_id as &ToSql,
+            &kind,
+            &repo_key_id,
+            &author_id,
+            &title,
+            &message.value.timestamp,
+            &message.value.timestamp,
+        ])?;
+
+    Ok(())
+}
+
+// Opens or closes issues and pull requests. Only the issue's author or the repo's author can.
+fn update_issues(connection: &Connection, message: &SsbMessage) -> Result<(), Error> {
+    let edits = match message.value.content["issues"].as_array() {
+        Some(edits) => edits,
+        None => return Ok(()),
+    };
+
+    let author_id = find_or_create_author(connection, &message.value.author)?;
+
+    for edit in edits {
+        let (issue, is_open) = match (edit["link"].as_str(), edit["open"].as_bool()) {
+            (Some(issue), Some(is_open)) => (issue, is_open),
+            _ => continue,
+        };
+
+        connection
+            .prepare_cached(
+                "
+                UPDATE git_issues SET is_open = ?, updated_time = ?
+                WHERE key_id = (SELECT id FROM keys WHERE key = ?)
+                AND updated_time <= ?
+                AND (
+                    author_id = ?
+                    OR repo_key_id IN (SELECT key_id FROM git_repos WHERE author_id = ?)
+                )
+                ",
+            )?
+            .execute(&[
+                &is_open as &ToSql,
+                &message.value.timestamp,
+                &issue,
+                &message.value.timestamp,
+                &author_id,
+                &author_id,
+            ])?;
+    }
+
+    Ok(())
+}
+
+fn rename_repo(connection: &Connection, message: &SsbMessage) -> Result<(), Error> {
+    let content = &message.value.content;
+
+    if let (Some(repo), Some(name)) = (content["about"].as_str(), content["name"].as_str()) {
+        let author_id = find_or_create_author(connection, &message.value.author)?;
+
+        connection
+            .prepare_cached(
+                "
+                UPDATE git_repos SET name = ?
+                WHERE key_id = (SELECT id FROM keys WHERE key = ?)
+                AND author_id = ?
+                ",
+            )?
+            .execute(&[&name as &ToSql, &repo, &author_id])?;
+    }
+
+    Ok(())
+}
+
+pub fn create_git_tables(connection: &Connection) -> Result<usize, Error> {
+    trace!("Creating git tables");
+
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS git_repos (
+          key_id INTEGER PRIMARY KEY,
+          author_id INTEGER,
+          name TEXT,
+          upstream_key_id INTEGER,
+          asserted_time REAL
+        )",
+        NO_PARAMS,
+    )?;
+
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS git_updates (
+          key_id INTEGER PRIMARY KEY,
+          repo_key_id INTEGER,
+          author_id INTEGER,
+          asserted_time REAL
+        )",
+        NO_PARAMS,
+    )?;
+
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS git_refs (
+          id INTEGER PRIMARY KEY,
+          repo_key_id INTEGER,
+          ref TEXT,
+          sha TEXT,
+          update_key_id INTEGER,
+          asserted_time REAL
+        )",
+        NO_PARAMS,
+    )?;
+
+    // `kind` is either issue or pull-request.
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS git_issues (
+          key_id INTEGER PRIMARY KEY,
+          kind TEXT,
+          repo_key_id INTEGER,
+          author_id INTEGER,
+          title TEXT,
+          is_open BOOLEAN,
+          asserted_time REAL,
+          updated_time REAL
+        )",
+        NO_PARAMS,
+    )
+}
+
+pub fn create_git_indices(connection: &Connection) -> Result<usize, Error> {
+    trace!("Creating git indices");
+
+    connection.execute(
+        "CREATE INDEX IF NOT EXISTS git_repos_author_id_index on git_repos (author_id)",
+        NO_PARAMS,
+    )?;
+    connection.execute(
+        "CREATE INDEX IF NOT EXISTS git_updates_repo_key_id_index on git_updates (repo_key_id)",
+        NO_PARAMS,
+    )?;
+    connection.execute(
+        "CREATE UNIQUE INDEX IF NOT EXISTS git_refs_repo_key_id_ref_index on git_refs (repo_key_id, ref)",
+        NO_PARAMS,
+    )?;
+    connection.execute(
+        "CREATE INDEX IF NOT EXISTS git_issues_repo_key_id_index on git_issues (repo_key_id, is_open)",
+        NO_PARAMS,
+    )
+}
+
+// Packs and their indexes are blob links of the update that pushed them.
+pub fn create_git_views(connection: &Connection) -> Result<usize, Error> {
+    trace!("Creating git views");
+
+    connection.execute(
+        "
+        CREATE VIEW IF NOT EXISTS git_packs AS
+        SELECT
+        git_updates.repo_key_id as repo_key_id,
+        git_updates.key_id as update_key_id,
+        blob_links_raw.link_to_blob_id as blob_id,
+        blobs.blob as blob
+        FROM git_updates
+        JOIN blob_links_raw ON blob_links_raw.link_from_key_id = git_updates.key_id
+        JOIN blobs ON blobs.id = blob_links_raw.link_to_blob_id
+        ",
+        NO_PARAMS,
+    )
+}
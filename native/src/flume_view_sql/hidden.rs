@@ -0,0 +1,232 @@
+use rusqlite::types::ToSql;
+use rusqlite::{Connection, Error, NO_PARAMS};
+use std::str::FromStr;
+
+use flume_view_sql::*;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum HiddenKind {
+    Message,
+    Author,
+    Blob,
+}
+
+impl HiddenKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            HiddenKind::Message => "message",
+            HiddenKind::Author => "author",
+            HiddenKind::Blob => "blob",
+        }
+    }
+}
+
+impl FromStr for HiddenKind {
+    type Err = failure::Error;
+
+    fn from_str(kind: &str) -> Result<HiddenKind, failure::Error> {
+        match kind {
+            "message" => Ok(HiddenKind::Message),
+            "author" => Ok(HiddenKind::Author),
+            "blob" => Ok(HiddenKind::Blob),
+            _ => Err(format_err!("Unknown kind of content to hide: {}", kind)),
+        }
+    }
+}
+
+// Content hidden locally. `id` is a message key, a feed id or a blob id depending on `kind`. When
+// `purge` is set the content of hidden messages is deleted from `messages_raw` too, and isn't
+// stored for hidden messages that are indexed later.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HiddenContent {
+    pub kind: HiddenKind,
+    pub id: String,
+    pub purge: bool,
+}
+
+// Hidden messages stay in `messages_raw`, but the `messages`, `links`, `blob_links` and `abouts`
+// views leave them out. Hiding a blob hides the messages that link to it.
+pub fn hide_content(connection: &Connection, content: &HiddenContent) -> Result<(), Error> {
+    connection.execute(
+        "INSERT OR REPLACE INTO hidden_content (kind, target, purge) VALUES (?, ?, ?)",
+        &[
+            &content.kind.as_str() as &ToSql,
+            &content.id,
+            &content.purge,
+        ],
+    )?;
+
+    if content.purge {
+        purge_messages(connection, content)?;
+    }
+
+    Ok(())
+}
+
+// Purged content can't come back until the view is rebuilt from the offset log.
+pub fn unhide_content(connection: &Connection, kind: HiddenKind, id: &str) -> Result<usize, Error> {
+    connection.execute(
+        "DELETE FROM hidden_content WHERE kind = ? AND target = ?",
+        &[&kind.as_str() as &ToSql, &id],
+    )
+}
+
+pub fn get_hidden_content(connection: &Connection) -> Result<Vec<HiddenContent>, Error> {
+    let mut stmt =
+        connection.prepare_cached("SELECT kind, target, purge FROM hidden_content ORDER BY id")?;
+
+    let rows = stmt.query_map(NO_PARAMS, |row| {
+        (
+            row.get::<usize, String>(0),
+            row.get::<usize, String>(1),
+            row.get::<usize, bool>(2),
+        )
+    })?;
+
+    let mut hidden = Vec::new();
+    for row in rows {
+        let (kind, id, purge) = row?;
+        if let Ok(kind) = kind.parse() {
+            hidden.push(HiddenContent { kind, id, purge });
+        }
+    }
+
+    Ok(hidden)
+}
+
+// Whether `message` was hidden with `purge` before it was indexed, so its content shouldn't be
+// stored.
+pub fn is_purged(connection: &Connection, message: &SsbMessage) -> Result<bool, Error> {
+    let mut stmt = connection.prepare_cached(
+        "SELECT COUNT(*) FROM hidden_content WHERE purge = 1 AND kind = ? AND target = ?",
+    )?;
+    let mut is_hidden = |kind: HiddenKind, id: &str| {
+        stmt.query_row(&[&kind.as_str() as &ToSql, &id], |row| {
+            row.get::<usize, i64>(0) > 0
+        })
+    };
+
+    if is_hidden(HiddenKind::Message, &message.key)?
+        || is_hidden(HiddenKind::Author, &message.value.author)?
+    {
+        return Ok(true);
+    }
+
+    let mut links = Vec::new();
+    find_values_in_object_by_key(&message.value.content, "link", &mut links);
+    for blob in links
+        .iter()
+        .filter_map(|link| link.as_str())
+        .filter(|link| link.starts_with('&'))
+    {
+        if is_hidden(HiddenKind::Blob, blob)? {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+fn purge_messages(connection: &Connection, content: &HiddenContent) -> Result<usize, Error> {
+    let condition = match content.kind {
+        HiddenKind::Message => "key_id = (SELECT id FROM keys WHERE key = ?)",
+        HiddenKind::Author => "author_id = (SELECT id FROM authors WHERE author = ?)",
+        HiddenKind::Blob => {
+            "key_id IN (
+              SELECT blob_links_raw.link_from_key_id FROM blob_links_raw
+              JOIN blobs ON blobs.id = blob_links_raw.link_to_blob_id
+              WHERE blobs.blob = ?
+            )"
+        }
+    };
+
+    // Text that was indexed for search is content too.
+    connection.execute(
+        &format!(
+            "DELETE FROM search_fts WHERE rowid IN (SELECT key_id FROM messages_raw WHERE {})",
+            condition
+        ),
+        &[&content.id],
+    )?;
+
+    connection.execute(
+        &format!(
+            "UPDATE messages_raw SET content = NULL, raw = NULL WHERE {}",
+            condition
+        ),
+        &[&content.id],
+    )
+}
+
+pub fn create_hidden_tables(connection: &Connection) -> Result<usize, Error> {
+    trace!("Creating hidden content tables");
+
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS hidden_content (
+          id INTEGER PRIMARY KEY,
+          kind TEXT,
+          target TEXT,
+          purge BOOLEAN
+        )",
+        NO_PARAMS,
+    )?;
+
+    connection.execute(
+        "CREATE UNIQUE INDEX IF NOT EXISTS hidden_content_kind_target_index on hidden_content (kind, target)",
+        NO_PARAMS,
+    )
+}
+
+// An SQL condition that's true when the message whose key id is in `key_id_column` is hidden: by
+// its key, by its author, because it links to a hidden blob, or because its author is blocked.
+pub fn hidden_message_condition(key_id_column: &str) -> String {
+    format!(
+        "
+        (EXISTS (
+          SELECT 1 FROM hidden_content
+          WHERE (
+            hidden_content.kind = 'message'
+            AND hidden_content.target = (SELECT key FROM keys WHERE id = {0})
+          ) OR (
+            hidden_content.kind = 'author'
+            AND hidden_content.target = (
+              SELECT authors.author FROM messages_raw AS hidden_messages
+              JOIN authors ON authors.id = hidden_messages.author_id
+              WHERE hidden_messages.key_id = {0}
+            )
+          ) OR (
+            hidden_content.kind = 'blob'
+            AND hidden_content.target IN (
+              SELECT blobs.blob FROM blob_links_raw AS hidden_blob_links
+              JOIN blobs ON blobs.id = hidden_blob_links.link_to_blob_id
+              WHERE hidden_blob_links.link_from_key_id = {0}
+            )
+          )
+        ) OR EXISTS (
+          SELECT 1 FROM blocked_authors
+          JOIN messages_raw AS blocked_messages
+          ON blocked_messages.author_id = blocked_authors.author_id
+          WHERE blocked_messages.key_id = {0}
+        ))
+        ",
+        key_id_column
+    )
+}
+
+// An SQL condition that's true when the author whose id is in `author_id_column` is hidden or
+// blocked.
+pub fn hidden_author_condition(author_id_column: &str) -> String {
+    format!(
+        "
+        (EXISTS (
+          SELECT 1 FROM hidden_content
+          JOIN authors ON authors.author = hidden_content.target
+          WHERE hidden_content.kind = 'author' AND authors.id = {0}
+        ) OR EXISTS (
+          SELECT 1 FROM blocked_authors WHERE blocked_authors.author_id = {0}
+        ))
+        ",
+        author_id_column
+    )
+}
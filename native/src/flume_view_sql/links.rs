@@ -16,7 +16,8 @@ pub fn create_links_tables(connection: &Connection) -> Result<usize, Error> {
 
 pub fn create_links_views(connection: &Connection) -> Result<usize, Error> {
     connection.execute(
-        "
+        &format!(
+            "
         CREATE VIEW IF NOT EXISTS links AS
         SELECT 
         links_raw.id as id, 
@@ -27,27 +28,32 @@ pub fn create_links_views(connection: &Connection) -> Result<usize, Error> {
         FROM links_raw 
         JOIN keys ON keys.id=links_raw.link_from_key_id
         JOIN keys AS keys2 ON keys2.id=links_raw.link_to_key_id
+        WHERE NOT {}
         ",
+            hidden_message_condition("links_raw.link_from_key_id")
+        ),
         NO_PARAMS,
     )
 }
 
-pub fn insert_links(connection: &Connection, links: &[&serde_json::Value], message_key_id: i64) {
+pub fn insert_links(
+    connection: &Connection,
+    links: &[&serde_json::Value],
+    message_key_id: i64,
+) -> Result<(), Error> {
     let mut insert_link_stmt = connection
-        .prepare_cached("INSERT INTO links_raw (link_from_key_id, link_to_key_id) VALUES (?, ?)")
-        .unwrap();
+        .prepare_cached("INSERT INTO links_raw (link_from_key_id, link_to_key_id) VALUES (?, ?)")?;
 
-    links
+    for link in links
         .iter()
-        .filter(|link| link.is_string())
-        .map(|link| link.as_str().unwrap())
+        .filter_map(|link| link.as_str())
         .filter(|link| link.starts_with('%'))
-        .map(|link| find_or_create_key(&connection, link).unwrap())
-        .for_each(|link_id| {
-            insert_link_stmt
-                .execute(&[&message_key_id, &link_id])
-                .unwrap();
-        });
+    {
+        let link_id = find_or_create_key(&connection, link)?;
+        insert_link_stmt.execute(&[&message_key_id, &link_id])?;
+    }
+
+    Ok(())
 }
 
 pub fn create_links_indices(connection: &Connection) -> Result<usize, Error> {
@@ -14,24 +14,25 @@ pub fn create_mentions_tables(connection: &Connection) -> Result<usize, Error> {
     )
 }
 
-pub fn insert_mentions(connection: &Connection, links: &[&serde_json::Value], message_key_id: i64) {
-    let mut insert_link_stmt = connection
-        .prepare_cached(
-            "INSERT INTO mentions_raw (link_from_key_id, link_to_author_id) VALUES (?, ?)",
-        )
-        .unwrap();
+pub fn insert_mentions(
+    connection: &Connection,
+    links: &[&serde_json::Value],
+    message_key_id: i64,
+) -> Result<(), Error> {
+    let mut insert_link_stmt = connection.prepare_cached(
+        "INSERT INTO mentions_raw (link_from_key_id, link_to_author_id) VALUES (?, ?)",
+    )?;
 
-    links
+    for link in links
         .iter()
-        .filter(|link| link.is_string())
-        .map(|link| link.as_str().unwrap())
+        .filter_map(|link| link.as_str())
         .filter(|link| link.starts_with('@'))
-        .map(|link| find_or_create_key(&connection, link).unwrap())
-        .for_each(|link_id| {
-            insert_link_stmt
-                .execute(&[&message_key_id, &link_id])
-                .unwrap();
-        });
+    {
+        let link_id = find_or_create_author(&connection, link)?;
+        insert_link_stmt.execute(&[&message_key_id, &link_id])?;
+    }
+
+    Ok(())
 }
 
 pub fn create_mentions_views(connection: &Connection) -> Result<usize, Error> {
@@ -9,15 +9,16 @@ pub fn insert_message(
     message: &SsbMessage,
     seq: i64,
     message_key_id: i64,
-    is_decrypted: bool,
+    is_valid: Option<bool>,
+    raw: Option<&[u8]>,
 ) -> Result<usize, Error> {
     trace!("prepare stmt");
-    let mut insert_msg_stmt = connection.prepare_cached("INSERT INTO messages_raw (flume_seq, key_id, seq, received_time, asserted_time, root_id, fork_id, author_id, content_type, content, is_decrypted) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)")?;
+    let mut insert_msg_stmt = connection.prepare_cached("INSERT INTO messages_raw (flume_seq, key_id, seq, received_time, asserted_time, sort_time, root_id, fork_id, author_id, content_type, content, is_decrypted, raw, previous_id, hash, signature, is_valid) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)")?;
 
     trace!("get root key id");
     let root_key_id = match message.value.content["root"] {
         Value::String(ref key) => {
-            let id = find_or_create_key(&connection, &key).unwrap();
+            let id = find_or_create_key(&connection, &key)?;
             Some(id)
         }
         _ => None,
@@ -26,15 +27,24 @@ pub fn insert_message(
     trace!("get fork key id");
     let fork_key_id = match message.value.content["fork"] {
         Value::String(ref key) => {
-            let id = find_or_create_key(&connection, &key).unwrap();
+            let id = find_or_create_key(&connection, &key)?;
             Some(id)
         }
         _ => None,
     };
 
+    trace!("get previous key id");
+    let previous_key_id = match message.value.previous {
+        Some(ref key) => Some(find_or_create_key(&connection, &key)?),
+        None => None,
+    };
+
     trace!("find or create author");
     let author_id = find_or_create_author(&connection, &message.value.author)?;
 
+    // Authors can claim any time, so a message can't sort later than when we received it.
+    let sort_time = message.value.timestamp.min(message.timestamp);
+
     trace!("insert message");
     insert_msg_stmt.execute(&[
         &seq as &ToSql,
@@ -42,12 +52,18 @@ pub fn insert_message(
         &message.value.sequence,
         &message.timestamp,
         &message.value.timestamp,
+        &sort_time,
         &root_key_id as &ToSql,
         &fork_key_id as &ToSql,
         &author_id,
         &message.value.content["type"].as_str() as &ToSql,
         &message.value.content as &ToSql,
-        &is_decrypted as &ToSql,
+        &message.is_decrypted as &ToSql,
+        &raw as &ToSql,
+        &previous_key_id as &ToSql,
+        &message.value.hash as &ToSql,
+        &message.value.signature as &ToSql,
+        &is_valid as &ToSql,
     ])
 }
 
@@ -60,12 +76,18 @@ pub fn create_messages_tables(connection: &Connection) -> Result<usize, Error> {
           seq INTEGER,
           received_time REAL,
           asserted_time REAL,
+          sort_time REAL,
           root_id INTEGER,
           fork_id INTEGER,
           author_id INTEGER,
           content_type TEXT,
           content JSON,
-          is_decrypted BOOLEAN
+          is_decrypted BOOLEAN,
+          raw BLOB,
+          previous_id INTEGER,
+          hash TEXT,
+          signature TEXT,
+          is_valid BOOLEAN
         )",
         NO_PARAMS,
     )
@@ -73,8 +95,10 @@ pub fn create_messages_tables(connection: &Connection) -> Result<usize, Error> {
 
 pub fn create_messages_views(connection: &Connection) -> Result<usize, Error> {
     trace!("Creating messages views");
+    // Messages that have been hidden are left out.
     connection.execute(
-        "
+        &format!(
+            "
         CREATE VIEW IF NOT EXISTS messages AS
         SELECT 
         flume_seq,
@@ -82,12 +106,14 @@ pub fn create_messages_views(connection: &Connection) -> Result<usize, Error> {
         seq,
         received_time,
         asserted_time,
+        sort_time,
         root_id,
         fork_id,
         author_id,
         content,
         content_type,
         is_decrypted,
+        is_valid,
         keys.key as key,
         root_keys.key as root,
         fork_keys.key as fork,
@@ -97,18 +123,71 @@ pub fn create_messages_views(connection: &Connection) -> Result<usize, Error> {
         LEFT JOIN keys AS root_keys ON root_keys.id=messages_raw.root_id
         LEFT JOIN keys AS fork_keys ON fork_keys.id=messages_raw.fork_id
         JOIN authors ON authors.id=messages_raw.author_id
+        WHERE NOT {}
         ",
+            hidden_message_condition("messages_raw.key_id")
+        ),
         NO_PARAMS,
     )
 }
 
+// Brings the messages tables of a db from before `sort_time` up to date in place, rather than
+// rebuilding it. It does nothing to a db that's already up to date, so it runs on every open.
+pub fn migrate_messages_tables(connection: &Connection) -> Result<(), Error> {
+    let has_sort_time: bool = connection.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info('messages_raw') WHERE name = 'sort_time'",
+        NO_PARAMS,
+        |row| row.get(0),
+    )?;
+
+    if !has_sort_time {
+        info!("Adding sort_time to messages_raw");
+        connection.execute(
+            "ALTER TABLE messages_raw ADD COLUMN sort_time REAL",
+            NO_PARAMS,
+        )?;
+        connection.execute(
+            "UPDATE messages_raw SET sort_time = MIN(asserted_time, received_time)",
+            NO_PARAMS,
+        )?;
+        // A view's columns are fixed when it's created.
+        connection.execute("DROP VIEW IF EXISTS messages", NO_PARAMS)?;
+        create_messages_views(connection)?;
+    }
+
+    // Replaced by the composite index on (asserted_time, flume_seq).
+    connection.execute("DROP INDEX IF EXISTS asserted_time_index", NO_PARAMS)?;
+    create_messages_indices(connection)?;
+
+    Ok(())
+}
+
 pub fn create_messages_indices(connection: &Connection) -> Result<usize, Error> {
     trace!("Creating messages indices");
     create_content_type_index(&connection)?;
     create_root_index(&connection)?;
+    create_author_seq_index(&connection)?;
+    create_asserted_time_index(&connection)?;
+    create_sort_time_index(&connection)?;
     create_author_index(connection)
 }
 
+fn create_asserted_time_index(connection: &Connection) -> Result<usize, Error> {
+    trace!("Creating asserted time index");
+    connection.execute(
+        "CREATE INDEX IF NOT EXISTS asserted_time_flume_seq_index on messages_raw (asserted_time, flume_seq)",
+        NO_PARAMS,
+    )
+}
+
+fn create_author_seq_index(connection: &Connection) -> Result<usize, Error> {
+    trace!("Creating author seq index");
+    connection.execute(
+        "CREATE INDEX IF NOT EXISTS author_id_seq_index on messages_raw (author_id, seq)",
+        NO_PARAMS,
+    )
+}
+
 fn create_author_index(connection: &Connection) -> Result<usize, Error> {
     trace!("Creating author index");
     connection.execute(
@@ -117,6 +196,14 @@ fn create_author_index(connection: &Connection) -> Result<usize, Error> {
     )
 }
 
+fn create_sort_time_index(connection: &Connection) -> Result<usize, Error> {
+    trace!("Creating sort time index");
+    connection.execute(
+        "CREATE INDEX IF NOT EXISTS sort_time_flume_seq_index on messages_raw (sort_time, flume_seq)",
+        NO_PARAMS,
+    )
+}
+
 fn create_root_index(connection: &Connection) -> Result<usize, Error> {
     trace!("Creating root index");
     connection.execute(
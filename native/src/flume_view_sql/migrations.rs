@@ -1,31 +1,89 @@
+use rusqlite::types::ToSql;
 use rusqlite::{Connection, Error, NO_PARAMS};
 
-const MIGRATION_VERSION_NUMBER: u32 = 1;
+// The version of the tables that aren't owned by a plugin: messages, keys, authors and migrations.
+// Changing it rebuilds the whole db, so prefer migrating the tables in place on open, like
+// `migrate_messages_tables`. Plugins have their own versions and are rebuilt on their own.
+const MIGRATION_VERSION_NUMBER: u32 = 13;
+const CORE_MIGRATION_NAME: &str = "core";
 
 pub fn create_migrations_tables(connection: &Connection) -> Result<usize, Error> {
     trace!("Creating migrations tables");
 
+    // `indexed_up_to` is the flume_seq of the last message a plugin has indexed.
     connection.execute(
         "CREATE TABLE IF NOT EXISTS migrations (
           id INTEGER PRIMARY KEY,
-          version INTEGER
+          name TEXT UNIQUE,
+          version INTEGER,
+          indexed_up_to INTEGER
         )",
         NO_PARAMS,
     )
 }
 
+// A db with an older schema, like a migrations table from before plugins had versions, is out of
+// date. Only errors if the db can't be read.
 pub fn is_db_up_to_date(connection: &Connection) -> Result<bool, Error> {
-    connection
-        .query_row_and_then("SELECT version FROM migrations LIMIT 1", NO_PARAMS, |row| {
-            row.get_checked(0)
-        })
-        .map(|version: u32| version == MIGRATION_VERSION_NUMBER)
-        .or(Ok(false))
+    let has_migrations_table: bool = connection.query_row(
+        "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = 'migrations'",
+        NO_PARAMS,
+        |row| row.get(0),
+    )?;
+
+    if !has_migrations_table {
+        return Ok(false);
+    }
+
+    let has_name_column: bool = connection.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info('migrations') WHERE name = 'name'",
+        NO_PARAMS,
+        |row| row.get(0),
+    )?;
+
+    if !has_name_column {
+        return Ok(false);
+    }
+
+    Ok(get_version(connection, CORE_MIGRATION_NAME)? == Some(MIGRATION_VERSION_NUMBER))
 }
 
 pub fn set_db_version(connection: &Connection) -> Result<usize, Error> {
+    set_version(connection, CORE_MIGRATION_NAME, MIGRATION_VERSION_NUMBER)
+}
+
+pub fn get_version(connection: &Connection, name: &str) -> Result<Option<u32>, Error> {
+    let version = connection.query_row_and_then(
+        "SELECT version FROM migrations WHERE name = ?",
+        &[name],
+        |row| row.get_checked(0),
+    );
+
+    match version {
+        Ok(version) => Ok(Some(version)),
+        Err(Error::QueryReturnedNoRows) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+// Records `version` for `name` and resets its watermark, as nothing has been indexed yet.
+pub fn set_version(connection: &Connection, name: &str, version: u32) -> Result<usize, Error> {
     connection.execute(
-        "INSERT INTO migrations (id, version) VALUES(0, ?)",
-        &[&MIGRATION_VERSION_NUMBER],
+        "INSERT OR REPLACE INTO migrations (name, version, indexed_up_to) VALUES (?, ?, NULL)",
+        &[&name as &ToSql, &version],
     )
 }
+
+pub fn get_indexed_up_to(connection: &Connection, name: &str) -> Result<Option<i64>, Error> {
+    connection.query_row_and_then(
+        "SELECT indexed_up_to FROM migrations WHERE name = ?",
+        &[name],
+        |row| row.get_checked(0),
+    )
+}
+
+pub fn set_indexed_up_to(connection: &Connection, name: &str, seq: i64) -> Result<usize, Error> {
+    connection
+        .prepare_cached("UPDATE migrations SET indexed_up_to = ? WHERE name = ?")?
+        .execute(&[&seq as &ToSql, &name])
+}
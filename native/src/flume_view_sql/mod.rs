@@ -1,5 +1,7 @@
 use failure::Error;
 use flumedb::flume_view::*;
+use flumedb::OffsetLogIter;
+use itertools::Itertools;
 
 use base64::decode;
 use rusqlite::types::ToSql;
@@ -12,59 +14,128 @@ use private_box::SecretKey;
 mod abouts;
 mod authors;
 mod blob_links;
+mod blob_store;
 mod blobs;
+mod blocks;
 mod branches;
+mod builtin_plugins;
 mod contacts;
+mod export;
+mod feeds;
+mod forks;
+mod gatherings;
+mod git;
+mod hidden;
 mod keys;
 mod links;
 mod mentions;
 mod messages;
 mod migrations;
+mod plugin;
+mod pubs;
 pub mod queries;
+mod reader;
+mod recovery;
+mod search;
+mod tags;
+mod validation;
 mod votes; //TODO un pub
 use self::abouts::*;
 use self::authors::*;
 use self::blob_links::*;
+pub use self::blob_store::*;
 use self::blobs::*;
+use self::blocks::*;
 use self::branches::*;
+use self::builtin_plugins::*;
 use self::contacts::*;
+pub use self::export::*;
+use self::feeds::*;
+use self::forks::*;
+use self::gatherings::*;
+use self::git::*;
+pub use self::hidden::*;
 use self::keys::*;
 use self::links::*;
 use self::mentions::*;
 use self::messages::*;
 use self::migrations::*;
+pub use self::plugin::*;
+use self::pubs::*;
 use self::queries::*;
+pub use self::reader::*;
+pub use self::recovery::*;
+use self::search::*;
+use self::tags::*;
+pub use self::validation::*;
 use self::votes::*;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SsbValue {
-    author: String,
-    sequence: u32,
-    timestamp: f64,
-    content: Value,
+    pub previous: Option<String>,
+    pub author: String,
+    pub sequence: u32,
+    pub timestamp: f64,
+    pub hash: Option<String>,
+    pub content: Value,
+    pub signature: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SsbMessage {
-    key: String,
-    value: SsbValue,
-    timestamp: f64,
+    pub key: String,
+    pub value: SsbValue,
+    pub timestamp: f64,
+    // Set when the content was private and we decrypted it with one of our secret keys.
+    #[serde(skip)]
+    pub is_decrypted: bool,
 }
 
 #[derive(Debug, Fail)]
 pub enum FlumeViewSqlError {
     #[fail(display = "Db failed integrity check")]
     DbFailedIntegrityCheck {},
+    #[fail(display = "More than one plugin is named {}", name)]
+    DuplicatePluginName { name: String },
+    // `id` is the message's key, or its flume_seq if it couldn't be parsed.
+    #[fail(display = "Malformed message {}: {}", id, reason)]
+    MalformedMessage { id: String, reason: String },
+}
+
+#[derive(Default)]
+pub struct FlumeViewSqlOptions {
+    pub check_integrity_on_open: bool,
+    // Check the signature and hash of every message as it's appended. Messages that fail are kept
+    // in `messages_raw` with `is_valid` set to false but aren't added to any other table.
+    pub validate_messages: bool,
+    // Indexes to keep up to date as well as the built in ones.
+    pub plugins: Vec<Box<SqlIndexPlugin>>,
+    // Leave the messages of feeds the local identity blocks out of the views, search and every
+    // plugin's tables. They're still kept in `messages_raw`, so unblocking brings them back.
+    pub exclude_blocked_feeds: bool,
 }
 
 pub struct FlumeViewSql {
-    pub connection: Connection,
+    connection: Connection,
     secret_keys: Vec<SecretKey>,
+    path: String,
+    pub_key: String,
+    last_rebuild: Option<RebuildEvent>,
+    validate_messages: bool,
+    exclude_blocked_feeds: bool,
+    plugins: Vec<Box<SqlIndexPlugin>>,
+    // Names of the plugins that are behind `messages_raw` after being rebuilt. They're caught up
+    // by `catch_up_plugins` and don't see new messages until then.
+    lagging_plugins: Vec<String>,
 }
 
 impl FlumeView for FlumeViewSql {
+    // `FlumeView` can't return errors, so they're logged and the item is left out. Use
+    // `append_batch` to handle them.
     fn append(&mut self, seq: Sequence, item: &[u8]) {
-        append_item(&self.connection, &self.secret_keys, seq, item).unwrap()
+        if let Err(err) = self.append_batch(&[(seq, item.to_vec())]) {
+            error!("Failed to append item at {} to the sql view: {}", seq, err);
+        }
     }
     fn latest(&self) -> Sequence {
         self.get_latest().unwrap()
@@ -85,78 +156,306 @@ impl FlumeViewSql {
         secret_keys: Vec<SecretKey>,
         pub_key: &str,
     ) -> Result<FlumeViewSql, Error> {
-        let mut connection = create_connection(path)?;
+        FlumeViewSql::new_with_options(path, secret_keys, pub_key, FlumeViewSqlOptions::default())
+    }
 
-        if let Ok(false) = is_db_up_to_date(&connection) {
-            info!("sqlite db is out of date. Deleting db and it will be rebuilt.");
-            std::fs::remove_file(path).unwrap();
+    pub fn new_with_options(
+        path: &str,
+        secret_keys: Vec<SecretKey>,
+        pub_key: &str,
+        options: FlumeViewSqlOptions,
+    ) -> Result<FlumeViewSql, Error> {
+        let mut plugins = builtin_plugins();
+        for plugin in options.plugins {
+            if plugins
+                .iter()
+                .any(|existing| existing.name() == plugin.name())
+            {
+                return Err(FlumeViewSqlError::DuplicatePluginName {
+                    name: plugin.name().to_string(),
+                }
+                .into());
+            }
+            plugins.push(plugin);
+        }
 
-            connection = create_connection(path)?;
+        let db_existed = std::fs::metadata(path)
+            .map(|metadata| metadata.len() > 0)
+            .unwrap_or(false);
 
-            create_tables(&connection)?;
-            create_indices(&connection)?;
-            create_views(&connection)?;
+        if options.validate_messages {
+            sodiumoxide::init().map_err(|_| format_err!("Failed to initialise sodiumoxide"))?;
+        }
 
-            set_db_version(&connection)?;
-            set_author_that_is_me(&connection, pub_key)?;
+        let mut view = FlumeViewSql {
+            connection: create_connection(path)?,
+            secret_keys,
+            path: path.to_string(),
+            pub_key: pub_key.to_string(),
+            last_rebuild: None,
+            validate_messages: options.validate_messages,
+            exclude_blocked_feeds: options.exclude_blocked_feeds,
+            plugins,
+            lagging_plugins: Vec::new(),
+        };
+
+        // The integrity check runs before anything else reads the db, so a corrupt db is moved
+        // aside rather than mistaken for an out of date one.
+        if options.check_integrity_on_open {
+            view.check_and_recover()?;
         }
 
-        set_pragmas(&connection);
+        match is_db_up_to_date(&view.connection) {
+            Ok(true) => {
+                let tx = view.connection.transaction()?;
+                migrate_messages_tables(&tx)?;
+                tx.commit()?;
+            }
+            Ok(false) if !db_existed => {
+                initialise_db(&view.connection, pub_key, &view.plugins)?;
+            }
+            Ok(false) => {
+                info!("sqlite db is out of date. Deleting db and it will be rebuilt.");
+                view.delete_and_replace_db(RebuildReason::OutOfDate)?;
+            }
+            Err(err) if options.check_integrity_on_open => {
+                warn!("sqlite db can't be read: {}. Moving it aside.", err);
+                view.recover(RebuildReason::Unreadable)?;
+            }
+            Err(err) => {
+                info!(
+                    "sqlite db can't be read: {}. Deleting db and it will be rebuilt.",
+                    err
+                );
+                view.delete_and_replace_db(RebuildReason::OutOfDate)?;
+            }
+        }
 
-        Ok(FlumeViewSql {
-            connection,
-            secret_keys,
-        })
-    }
+        set_pragmas(&view.connection);
+
+        rebuild_outdated_plugins(&view.connection, &view.plugins)?;
+        view.lagging_plugins = find_lagging_plugins(&view.connection, &view.plugins)?;
 
-    pub fn get_seq_by_key(&mut self, key: &str) -> Result<i64, Error> {
-        let mut stmt = self
-            .connection
-            .prepare("SELECT flume_seq FROM messages_raw JOIN keys ON messages_raw.key_id=keys.id WHERE keys.key=?1")?;
+        // Catches up with blocks indexed while the option was off, or undoes them if it's now off.
+        view.update_blocked_feeds()?;
 
-        stmt.query_row(&[key], |row| row.get(0))
-            .map_err(|err| err.into())
+        Ok(view)
     }
 
-    pub fn get_seqs_by_type(&mut self, content_type: &str) -> Result<Vec<i64>, Error> {
-        let mut stmt = self
-            .connection
-            .prepare("SELECT flume_seq FROM messages_raw WHERE content_type=?1")?;
+    // The event describing why the db was last rebuilt by this view, if it was.
+    pub fn last_rebuild(&self) -> Option<&RebuildEvent> {
+        self.last_rebuild.as_ref()
+    }
 
-        let rows = stmt.query_map(&[content_type], |row| row.get(0))?;
+    // Runs the integrity check and, if it fails, moves the corrupted db aside and creates a fresh
+    // one. The fresh db is empty so `get_latest` returns 0 and the next call to process the
+    // offset log rebuilds the view from the start.
+    pub fn check_and_recover(&mut self) -> Result<Option<RebuildEvent>, Error> {
+        if self.check_db_integrity().is_ok() {
+            return Ok(None);
+        }
 
-        let seqs = rows.fold(Vec::<i64>::new(), |mut vec, row| {
-            vec.push(row.unwrap());
-            vec
-        });
+        self.recover(RebuildReason::FailedIntegrityCheck).map(Some)
+    }
 
-        Ok(seqs)
+    // Deletes the db and starts again with an empty one. The next call to process the offset log
+    // rebuilds the view from the start.
+    pub fn rebuild(&mut self) -> Result<RebuildEvent, Error> {
+        self.delete_and_replace_db(RebuildReason::Requested)
     }
 
-    pub fn get_seqs_by_author(&mut self, author: &str) -> Result<Vec<i64>, Error> {
-        let mut stmt = self
-            .connection
-            .prepare("SELECT flume_seq FROM messages_raw JOIN authors ON messages_raw.author_id=authors.id WHERE author=?1")?;
+    fn delete_and_replace_db(&mut self, reason: RebuildReason) -> Result<RebuildEvent, Error> {
+        self.replace_db(reason, |path| {
+            std::fs::remove_file(path)?;
+            remove_db_sidecar_files(path);
+            Ok(None)
+        })
+    }
 
-        let rows = stmt.query_map(&[author], |row| row.get(0))?;
+    fn recover(&mut self, reason: RebuildReason) -> Result<RebuildEvent, Error> {
+        self.replace_db(reason, |path| move_db_aside(path).map(Some))
+    }
 
-        let seqs = rows.fold(Vec::<i64>::new(), |mut vec, row| {
-            vec.push(row.unwrap());
-            vec
-        });
+    fn replace_db<F>(&mut self, reason: RebuildReason, remove_db: F) -> Result<RebuildEvent, Error>
+    where
+        F: FnOnce(&str) -> Result<Option<String>, Error>,
+    {
+        // Content the user hid is kept, as it isn't in the offset log. A corrupted db might not
+        // be readable, in which case it's lost.
+        let hidden = get_hidden_content(&self.connection).unwrap_or_default();
+
+        // Close the connection to the old db before removing it, and reopen it if that fails so
+        // the view isn't left on an in-memory db.
+        let old_connection = std::mem::replace(&mut self.connection, Connection::open_in_memory()?);
+        drop(old_connection);
+
+        let moved_to = match remove_db(&self.path) {
+            Ok(moved_to) => moved_to,
+            Err(err) => {
+                self.connection = create_connection(&self.path)?;
+                set_pragmas(&self.connection);
+                return Err(err);
+            }
+        };
+
+        let connection = create_connection(&self.path)?;
+        initialise_db(&connection, &self.pub_key, &self.plugins)?;
+        restore_hidden_content(&connection, &hidden)?;
+        set_pragmas(&connection);
+        self.connection = connection;
+        self.lagging_plugins.clear();
+
+        let event = RebuildEvent::new(reason, &self.path, moved_to);
+        event.emit();
+        self.last_rebuild = Some(event.clone());
+
+        Ok(event)
+    }
+
+    // Appends up to `num_items` new entries from the offset log at `log_path`, or all of them if
+    // `num_items` is -1. Returns how many entries were appended.
+    pub fn process(&mut self, log_path: &str, num_items: i64) -> Result<usize, Error> {
+        let latest = self.get_latest()?;
+
+        //If the latest is 0, we haven't got anything in the db. Don't skip the very first
+        //element in the offset log. I know this isn't super nice. It could be refactored later.
+        let num_to_skip = match latest {
+            0 => 0,
+            _ => 1,
+        };
+        let file = std::fs::File::open(log_path)?;
+
+        let items_to_take = match num_items {
+            -1 => std::usize::MAX,
+            n => n as usize,
+        };
+
+        let chunks = OffsetLogIter::<u32>::with_starting_offset(file, latest)
+            .skip(num_to_skip)
+            .take(items_to_take)
+            .map(|data| (data.offset + latest, data.data)) //TODO log_latest might not be the right thing
+            .chunks(1000);
+
+        let mut num_processed = 0;
+
+        for chunk in chunks.into_iter() {
+            let items = chunk.collect_vec();
+            self.append_batch(&items)?;
+            num_processed += items.len();
+        }
 
-        Ok(seqs)
+        Ok(num_processed)
     }
 
-    pub fn append_batch(&mut self, items: &[(Sequence, Vec<u8>)]) {
+    pub fn append_batch(&mut self, items: &[(Sequence, Vec<u8>)]) -> Result<(), Error> {
         trace!("Start batch append");
-        let tx = self.connection.transaction().unwrap();
+        let plugins = active_plugins(&self.plugins, &self.lagging_plugins);
+        let tx = self.connection.transaction()?;
+        let mut blocks_changed = false;
 
         for item in items {
-            append_item(&tx, &self.secret_keys, item.0, &item.1).unwrap();
+            blocks_changed |= append_item(
+                &tx,
+                &self.secret_keys,
+                &self.pub_key,
+                self.validate_messages,
+                &plugins,
+                item.0,
+                &item.1,
+            )?;
+        }
+
+        if let Some(latest) = items.iter().map(|item| item.0).max() {
+            set_plugins_indexed_up_to(&tx, &plugins, latest as i64)?;
+        }
+
+        let rebuilt = if blocks_changed {
+            reprocess_blocked_feeds(
+                &tx,
+                &self.secret_keys,
+                &self.plugins,
+                &self.lagging_plugins,
+                self.exclude_blocked_feeds,
+            )?
+        } else {
+            Vec::new()
+        };
+
+        tx.commit()?;
+        add_lagging_plugins(&mut self.lagging_plugins, rebuilt);
+        Ok(())
+    }
+
+    // Names of the plugins that were rebuilt and haven't caught up with `messages_raw` yet.
+    pub fn lagging_plugins(&self) -> &[String] {
+        &self.lagging_plugins
+    }
+
+    // Indexes up to `num_items` messages from `messages_raw` into each lagging plugin, or all of
+    // them if `num_items` is -1. Call it with a non-zero `num_items` until it returns 0 to bring
+    // rebuilt plugins up to date. 0 does nothing, like it does for `process`. The rest of the view
+    // can be appended to and queried in the meantime.
+    pub fn catch_up_plugins(&mut self, num_items: i64) -> Result<usize, Error> {
+        if num_items == 0 || self.lagging_plugins.is_empty() {
+            return Ok(0);
         }
 
-        tx.commit().unwrap();
+        let tx = self.connection.transaction()?;
+        let latest = get_latest(&tx)? as i64;
+        let mut num_processed = 0;
+        let mut caught_up = Vec::new();
+
+        for plugin in self.plugins.iter() {
+            if !self
+                .lagging_plugins
+                .iter()
+                .any(|name| name == plugin.name())
+            {
+                continue;
+            }
+
+            let indexed_up_to = get_indexed_up_to(&tx, plugin.name())?.unwrap_or(-1);
+
+            let mut stmt = tx.prepare_cached(
+                "SELECT flume_seq, key_id, raw FROM messages_raw WHERE flume_seq > ? AND is_valid IS NOT 0 AND raw IS NOT NULL AND author_id NOT IN (SELECT author_id FROM blocked_authors) ORDER BY flume_seq ASC LIMIT ?",
+            )?;
+            let rows = stmt
+                .query_map(&[&indexed_up_to, &num_items], |row| {
+                    (
+                        row.get::<usize, i64>(0),
+                        row.get::<usize, i64>(1),
+                        row.get::<usize, Vec<u8>>(2),
+                    )
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+
+            for (_, key_id, raw) in &rows {
+                let message: SsbMessage = serde_json::from_slice(raw)?;
+                let message = attempt_decryption(message, &self.secret_keys)?;
+                plugin.on_message(&tx, &message, *key_id)?;
+            }
+            num_processed += rows.len();
+
+            if rows.len() < num_items as usize {
+                info!("sql view plugin {} has caught up", plugin.name());
+                set_indexed_up_to(&tx, plugin.name(), latest)?;
+                caught_up.push(plugin.name().to_string());
+            } else if let Some((flume_seq, _, _)) = rows.last() {
+                set_indexed_up_to(&tx, plugin.name(), *flume_seq)?;
+            }
+        }
+
+        tx.commit()?;
+
+        self.lagging_plugins
+            .retain(|name| !caught_up.contains(name));
+
+        // Blocks aren't acted on while the contacts plugin is behind.
+        if caught_up.iter().any(|name| name == "contacts") {
+            self.update_blocked_feeds()?;
+        }
+
+        Ok(num_processed)
     }
 
     pub fn check_db_integrity(&mut self) -> Result<(), Error> {
@@ -174,19 +473,54 @@ impl FlumeViewSql {
     }
 
     pub fn get_latest(&self) -> Result<Sequence, Error> {
-        let mut stmt = self
-            .connection
-            .prepare_cached("SELECT MAX(flume_seq) FROM messages_raw")?;
-
-        stmt.query_row(NO_PARAMS, |row| {
-            let res: i64 = row.get_checked(0).unwrap_or(0);
-            trace!("got latest seq from db: {}", res);
-            res as Sequence
-        })
-        .map_err(|err| err.into())
+        get_latest(&self.connection)
+    }
+
+    // Hides content locally without touching the offset log. See `HiddenContent`.
+    pub fn hide(&mut self, content: &HiddenContent) -> Result<(), Error> {
+        let tx = self.connection.transaction()?;
+        hide_content(&tx, content)?;
+        tx.commit().map_err(|err| err.into())
+    }
+
+    pub fn unhide(&mut self, kind: HiddenKind, id: &str) -> Result<(), Error> {
+        unhide_content(&self.connection, kind, id)?;
+        Ok(())
+    }
+
+    fn update_blocked_feeds(&mut self) -> Result<(), Error> {
+        let tx = self.connection.transaction()?;
+        let rebuilt = reprocess_blocked_feeds(
+            &tx,
+            &self.secret_keys,
+            &self.plugins,
+            &self.lagging_plugins,
+            self.exclude_blocked_feeds,
+        )?;
+
+        tx.commit()?;
+        add_lagging_plugins(&mut self.lagging_plugins, rebuilt);
+        Ok(())
+    }
+
+    // Opens a new pool of read only connections to the view. All the typed queries go through the
+    // reader so they can run on other threads while this view is indexing.
+    pub fn reader(&self) -> Result<SqlReader, Error> {
+        SqlReader::new(&self.path)
     }
 }
 
+pub fn get_latest(connection: &Connection) -> Result<Sequence, Error> {
+    let mut stmt = connection.prepare_cached("SELECT MAX(flume_seq) FROM messages_raw")?;
+
+    stmt.query_row(NO_PARAMS, |row| {
+        let res: i64 = row.get_checked(0).unwrap_or(0);
+        trace!("got latest seq from db: {}", res);
+        res as Sequence
+    })
+    .map_err(|err| err.into())
+}
+
 fn find_values_in_object_by_key<'a>(
     obj: &'a serde_json::Value,
     key: &str,
@@ -215,15 +549,26 @@ fn find_values_in_object_by_key<'a>(
     }
 }
 
-fn attempt_decryption(mut message: SsbMessage, secret_keys: &[SecretKey]) -> (bool, SsbMessage) {
+fn attempt_decryption(
+    mut message: SsbMessage,
+    secret_keys: &[SecretKey],
+) -> Result<SsbMessage, Error> {
     let mut is_decrypted = false;
 
     message = match message.value.content["type"] {
         Value::Null => {
             let content = message.value.content.clone();
-            let strrr = &content.as_str().unwrap().trim_end_matches(".box");
-
-            let bytes = decode(strrr).unwrap();
+            let key = message.key.clone();
+            let malformed = |reason: String| FlumeViewSqlError::MalformedMessage {
+                id: key.clone(),
+                reason,
+            };
+            let boxed = content
+                .as_str()
+                .ok_or_else(|| malformed("content has no type".to_string()))?
+                .trim_end_matches(".box");
+
+            let bytes = decode(boxed).map_err(|err| malformed(err.to_string()))?;
 
             for secret_key in secret_keys {
                 message.value.content = private_box::decrypt(&bytes, secret_key)
@@ -243,46 +588,301 @@ fn attempt_decryption(mut message: SsbMessage, secret_keys: &[SecretKey]) -> (bo
         _ => message,
     };
 
-    (is_decrypted, message)
+    message.is_decrypted = is_decrypted;
+    Ok(message)
 }
 
+// Returns true when the message is a contact message from the local identity, which might change
+// the feeds it blocks.
 fn append_item(
     connection: &Connection,
     secret_keys: &[SecretKey],
+    pub_key: &str,
+    validate: bool,
+    plugins: &[&SqlIndexPlugin],
     seq: Sequence,
     item: &[u8],
-) -> Result<(), Error> {
-    let message: SsbMessage = serde_json::from_slice(item).unwrap();
+) -> Result<bool, Error> {
+    let message: SsbMessage =
+        serde_json::from_slice(item).map_err(|err| FlumeViewSqlError::MalformedMessage {
+            id: format!("at flume_seq {}", seq),
+            reason: err.to_string(),
+        })?;
+
+    // Validation has to happen before decryption, on the value as it was signed.
+    let is_valid = if validate {
+        let item_value: Value = serde_json::from_slice(item)?;
+        match validate_message(&message.key, &item_value["value"]) {
+            Ok(()) => Some(true),
+            Err(err) => {
+                warn!("Invalid message {}: {}", message.key, err);
+                Some(false)
+            }
+        }
+    } else {
+        None
+    };
 
-    let (is_decrypted, message) = attempt_decryption(message, secret_keys);
+    let mut message = attempt_decryption(message, secret_keys)?;
 
-    let message_key_id = find_or_create_key(&connection, &message.key).unwrap();
+    let message_key_id = find_or_create_key(&connection, &message.key)?;
 
-    // votes are a kind of backlink, but we want to put them in their own table.
-    match &message.value.content["type"] {
-        Value::String(type_string) if type_string == "vote" => {
-            insert_or_update_votes(connection, &message);
-        }
-        _ => {
-            let mut links = Vec::new();
-            find_values_in_object_by_key(&message.value.content, "link", &mut links);
-            insert_links(connection, links.as_slice(), message_key_id);
-            insert_mentions(connection, links.as_slice(), message_key_id);
-            insert_blob_links(connection, links.as_slice(), message_key_id);
-        }
+    // Purged messages are kept without their content, and there's nothing to index.
+    let purged = is_purged(connection, &message)?;
+    let blocked = is_author_blocked(connection, &message)?;
+    if purged {
+        message.value.content = Value::Null;
     }
 
-    insert_branches(connection, &message, message_key_id);
     insert_message(
         connection,
         &message,
         seq as i64,
         message_key_id,
-        is_decrypted,
+        is_valid,
+        if purged { None } else { Some(item) },
+    )?;
+
+    if is_valid == Some(false) || purged {
+        return Ok(false);
+    }
+
+    // Blob links are core so hiding a blob hides every message linking to it, whichever plugins
+    // are running. Blocked feeds are filtered out by `hidden_message_condition` instead.
+    let mut links = Vec::new();
+    find_values_in_object_by_key(&message.value.content, "link", &mut links);
+    insert_blob_links(connection, links.as_slice(), message_key_id)?;
+    insert_blob_metadata(connection, &message.value.content)?;
+
+    if blocked {
+        return Ok(false);
+    }
+
+    for plugin in plugins {
+        plugin.on_message(connection, &message, message_key_id)?;
+    }
+
+    Ok(message.value.author == pub_key && message.value.content["type"] == "contact")
+}
+
+fn active_plugins<'a>(
+    plugins: &'a [Box<SqlIndexPlugin>],
+    lagging_plugins: &[String],
+) -> Vec<&'a SqlIndexPlugin> {
+    plugins
+        .iter()
+        .filter(|plugin| !lagging_plugins.iter().any(|name| name == plugin.name()))
+        .map(|plugin| plugin.as_ref())
+        .collect()
+}
+
+fn set_plugins_indexed_up_to(
+    connection: &Connection,
+    plugins: &[&SqlIndexPlugin],
+    seq: i64,
+) -> Result<(), Error> {
+    for plugin in plugins {
+        set_indexed_up_to(connection, plugin.name(), seq)?;
+    }
+    Ok(())
+}
+
+// Brings `blocked_authors` in line with the feeds the local identity blocks, or empties it when
+// blocked feeds aren't excluded. What was indexed from newly blocked feeds is removed, and unblocked
+// feeds are indexed again. Returns the names of the plugins that had to be rebuilt instead.
+fn reprocess_blocked_feeds(
+    connection: &Connection,
+    secret_keys: &[SecretKey],
+    plugins: &[Box<SqlIndexPlugin>],
+    lagging_plugins: &[String],
+    exclude_blocked_feeds: bool,
+) -> Result<Vec<String>, Error> {
+    // Blocks might be missing until the contacts plugin has caught up.
+    if lagging_plugins.iter().any(|name| name == "contacts") {
+        return Ok(Vec::new());
+    }
+
+    let blocked = if exclude_blocked_feeds {
+        find_blocked_authors(connection)?
+    } else {
+        Vec::new()
+    };
+    let previously_blocked = get_blocked_authors(connection)?;
+    let mut rebuilt = Vec::new();
+
+    for author_id in blocked
+        .iter()
+        .filter(|id| !previously_blocked.contains(*id))
+    {
+        info!("Removing blocked feed {} from the sql view", author_id);
+        set_author_blocked(connection, *author_id, true)?;
+        remove_feed_from_plugins(connection, plugins, *author_id, &mut rebuilt)?;
+    }
+
+    for author_id in previously_blocked
+        .iter()
+        .filter(|id| !blocked.contains(*id))
+    {
+        info!("Indexing unblocked feed {} again", author_id);
+        set_author_blocked(connection, *author_id, false)?;
+        remove_feed_from_plugins(connection, plugins, *author_id, &mut rebuilt)?;
+        reindex_feed(connection, secret_keys, plugins, &rebuilt, *author_id)?;
+    }
+
+    Ok(rebuilt)
+}
+
+// Plugins that can't remove a feed are rebuilt and added to `rebuilt`.
+fn remove_feed_from_plugins(
+    connection: &Connection,
+    plugins: &[Box<SqlIndexPlugin>],
+    author_id: i64,
+    rebuilt: &mut Vec<String>,
+) -> Result<(), Error> {
+    for plugin in plugins {
+        if rebuilt.iter().any(|name| name == plugin.name()) {
+            continue;
+        }
+
+        if !plugin.remove_feed(connection, author_id)? {
+            info!(
+                "sql view plugin {} can't remove a feed. Rebuilding it.",
+                plugin.name()
+            );
+            plugin.drop_schema(connection)?;
+            plugin.create_schema(connection)?;
+            set_version(connection, plugin.name(), plugin.version())?;
+            rebuilt.push(plugin.name().to_string());
+        }
+    }
+
+    Ok(())
+}
+
+// Indexes the feed's messages into each plugin again, up to where the plugin has got to.
+fn reindex_feed(
+    connection: &Connection,
+    secret_keys: &[SecretKey],
+    plugins: &[Box<SqlIndexPlugin>],
+    rebuilt: &[String],
+    author_id: i64,
+) -> Result<(), Error> {
+    let mut stmt = connection.prepare_cached(
+        "SELECT flume_seq, key_id, raw FROM messages_raw WHERE author_id = ? AND is_valid IS NOT 0 AND raw IS NOT NULL ORDER BY flume_seq ASC",
+    )?;
+    let rows = stmt
+        .query_map(&[&author_id], |row| {
+            (
+                row.get::<usize, i64>(0),
+                row.get::<usize, i64>(1),
+                row.get::<usize, Vec<u8>>(2),
+            )
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut messages = Vec::new();
+    for (flume_seq, key_id, raw) in rows {
+        let message: SsbMessage = serde_json::from_slice(&raw)?;
+        messages.push((flume_seq, key_id, attempt_decryption(message, secret_keys)?));
+    }
+
+    for plugin in plugins {
+        if rebuilt.iter().any(|name| name == plugin.name()) {
+            continue;
+        }
+
+        let indexed_up_to = get_indexed_up_to(connection, plugin.name())?.unwrap_or(-1);
+        for (_, key_id, message) in messages
+            .iter()
+            .take_while(|(flume_seq, _, _)| *flume_seq <= indexed_up_to)
+        {
+            plugin.on_message(connection, message, *key_id)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn add_lagging_plugins(lagging_plugins: &mut Vec<String>, names: Vec<String>) {
+    for name in names {
+        if !lagging_plugins.contains(&name) {
+            lagging_plugins.push(name);
+        }
+    }
+}
+
+// Drops and recreates the tables of plugins that are new or whose version has changed. They're
+// left with nothing indexed, so `find_lagging_plugins` will find them.
+fn rebuild_outdated_plugins(
+    connection: &Connection,
+    plugins: &[Box<SqlIndexPlugin>],
+) -> Result<(), Error> {
+    for plugin in plugins {
+        if get_version(connection, plugin.name())? == Some(plugin.version()) {
+            continue;
+        }
+
+        info!(
+            "sql view plugin {} is out of date. Rebuilding it.",
+            plugin.name()
+        );
+        plugin.drop_schema(connection)?;
+        plugin.create_schema(connection)?;
+        set_version(connection, plugin.name(), plugin.version())?;
+    }
+
+    Ok(())
+}
+
+fn find_lagging_plugins(
+    connection: &Connection,
+    plugins: &[Box<SqlIndexPlugin>],
+) -> Result<Vec<String>, Error> {
+    let latest: Option<i64> = connection.query_row(
+        "SELECT MAX(flume_seq) FROM messages_raw",
+        NO_PARAMS,
+        |row| row.get(0),
     )?;
-    insert_or_update_contacts(connection, &message, message_key_id, is_decrypted);
-    insert_abouts(connection, &message, message_key_id);
 
+    let latest = match latest {
+        Some(latest) => latest,
+        None => return Ok(Vec::new()),
+    };
+
+    let mut lagging_plugins = Vec::new();
+    for plugin in plugins {
+        if get_indexed_up_to(connection, plugin.name())?.unwrap_or(-1) < latest {
+            lagging_plugins.push(plugin.name().to_string());
+        }
+    }
+
+    Ok(lagging_plugins)
+}
+
+fn initialise_db(
+    connection: &Connection,
+    pub_key: &str,
+    plugins: &[Box<SqlIndexPlugin>],
+) -> Result<(), Error> {
+    create_tables(connection)?;
+    create_indices(connection)?;
+    create_views(connection)?;
+
+    for plugin in plugins {
+        plugin.create_schema(connection)?;
+        set_version(connection, plugin.name(), plugin.version())?;
+    }
+
+    set_db_version(connection)?;
+    set_author_that_is_me(connection, pub_key)?;
+
+    Ok(())
+}
+
+fn restore_hidden_content(connection: &Connection, hidden: &[HiddenContent]) -> Result<(), Error> {
+    for content in hidden {
+        hide_content(connection, content)?;
+    }
     Ok(())
 }
 
@@ -293,45 +893,39 @@ fn set_pragmas(connection: &Connection) {
     connection
         .execute("PRAGMA page_size = 4096", NO_PARAMS)
         .unwrap();
+    // WAL lets the read only connections of a `SqlReader` query while we're writing.
+    connection
+        .query_row("PRAGMA journal_mode = WAL", NO_PARAMS, |row| {
+            row.get::<usize, String>(0)
+        })
+        .unwrap();
 }
 
+// The tables every plugin depends on. Derived tables are created by their plugins.
 fn create_tables(connection: &Connection) -> Result<(), Error> {
     create_migrations_tables(connection)?;
     create_messages_tables(connection)?;
     create_authors_tables(connection)?;
     create_keys_tables(connection)?;
-    create_links_tables(connection)?;
-    create_contacts_tables(connection)?;
-    create_branches_tables(connection)?;
-    create_mentions_tables(connection)?;
-    create_abouts_tables(connection)?;
+    create_hidden_tables(connection)?;
+    create_blocks_tables(connection)?;
     create_blobs_tables(connection)?;
     create_blob_links_tables(connection)?;
-    create_votes_tables(connection)?;
 
     Ok(())
 }
 
 fn create_views(connection: &Connection) -> Result<(), Error> {
     create_messages_views(connection)?;
-    create_links_views(connection)?;
     create_blob_links_views(connection)?;
-    create_abouts_views(connection)?;
-    create_mentions_views(connection)?;
-    create_votes_indices(connection)?;
     Ok(())
 }
 
 fn create_indices(connection: &Connection) -> Result<(), Error> {
     create_messages_indices(connection)?;
-    create_links_indices(connection)?;
-    create_blob_links_indices(connection)?;
-    create_contacts_indices(connection)?;
     create_keys_indices(connection)?;
-    create_branches_indices(connection)?;
     create_authors_indices(connection)?;
-    create_abouts_indices(connection)?;
-    create_mentions_indices(connection)?;
+    create_blob_links_indices(connection)?;
     Ok(())
 }
 
@@ -401,15 +995,98 @@ mod test {
 }
 "#####;
         view.append(expected_seq, jsn.as_bytes());
-        let seq = view
+
+        let reader = view.reader().unwrap();
+        let seq = reader
             .get_seq_by_key("%KKPLj1tWfuVhCvgJz2hG/nIsVzmBRzUJaqHv+sb+n1c=.sha256")
             .unwrap();
         assert_eq!(seq, expected_seq as i64);
 
-        let seqs = view.get_seqs_by_type("post").unwrap();
+        let seqs = reader.get_seqs_by_type("post").unwrap();
         assert_eq!(seqs[0], expected_seq as i64);
     }
 
+    #[test]
+    fn typed_queries() {
+        let filename = "/tmp/test_typed_queries.sqlite3";
+        let keys = Vec::new();
+        std::fs::remove_file(filename.clone())
+            .or::<Result<()>>(Ok(()))
+            .unwrap();
+
+        let mut view = FlumeViewSql::new(filename, keys, "").unwrap();
+        let jsn = r#####"{
+  "key": "%KKPLj1tWfuVhCvgJz2hG/nIsVzmBRzUJaqHv+sb+n1c=.sha256",
+  "value": {
+    "previous": "%xsMQA2GrsZew0GSxmDSBaoxDafVaUJ07YVaDGcp65a4=.sha256",
+    "author": "@QlCTpvY7p9ty2yOFrv1WU1AE88aoQc4Y7wYal7PFc+w=.ed25519",
+    "sequence": 4797,
+    "timestamp": 1543958997985,
+    "hash": "sha256",
+    "content": {
+      "type": "post",
+      "root": "%9EdpeKC5CgzpQs/x99CcnbD3n6ugUlwm19F7ZTqMh5w=.sha256",
+      "branch": "%sQV8QpyUNvh7fBAs2ts00Qo2gj44CQBmwonWJzm+AeM=.sha256",
+      "reply": {
+        "%9EdpeKC5CgzpQs/x99CcnbD3n6ugUlwm19F7ZTqMh5w=.sha256": "@+UMKhpbzXAII+2/7ZlsgkJwIsxdfeFi36Z5Rk1gCfY0=.ed25519",
+        "%sQV8QpyUNvh7fBAs2ts00Qo2gj44CQBmwonWJzm+AeM=.sha256": "@vzoU7/XuBB5B0xueC9NHFr9Q76VvPktD9GUkYgN9lAc=.ed25519"
+      },
+      "channel": null,
+      "recps": null,
+      "text": "If I understand correctly, cjdns overlaying over old IP (which is basically all of the cjdns uses so far) still requires old IP addresses to introduce you to the cjdns network, so the chicken and egg problem is still there.",
+      "mentions": []
+    },
+    "signature": "mi5j/buYZdsiH8l6CVWRqdBKe+0UG6tVTOoVVjMhYl38Nkmb8wiIEfe7zu0JWuiHkaAIq+0/ZqYr6aV14j4fAw==.sig.ed25519"
+  },
+  "timestamp": 1543959001933
+}
+"#####;
+        view.append(1234, jsn.as_bytes());
+
+        let reader = view.reader().unwrap();
+
+        let results = reader.search("cjdns", 10).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].seq, 4797);
+
+        let thread = reader
+            .thread("%9EdpeKC5CgzpQs/x99CcnbD3n6ugUlwm19F7ZTqMh5w=.sha256")
+            .unwrap();
+        assert_eq!(thread.len(), 1);
+        assert_eq!(
+            thread[0].key,
+            "%KKPLj1tWfuVhCvgJz2hG/nIsVzmBRzUJaqHv+sb+n1c=.sha256"
+        );
+
+        let query = queries::LiveQuery::ThreadReplies {
+            root: "%9EdpeKC5CgzpQs/x99CcnbD3n6ugUlwm19F7ZTqMh5w=.sha256".to_string(),
+        };
+        assert_eq!(reader.live_query_results(&query, 0, 1234).unwrap().len(), 1);
+        assert_eq!(
+            reader.live_query_results(&query, 1234, 2000).unwrap().len(),
+            0
+        );
+    }
+
+    #[test]
+    fn reader_is_read_only() {
+        let filename = "/tmp/test_reader.sqlite3";
+        let keys = Vec::new();
+        std::fs::remove_file(filename.clone())
+            .or::<Result<()>>(Ok(()))
+            .unwrap();
+
+        let view = FlumeViewSql::new(filename, keys, "").unwrap();
+        let reader = view.reader().unwrap();
+
+        assert_eq!(reader.get_latest().unwrap(), 0);
+
+        let connection = reader.connection().unwrap();
+        assert!(connection
+            .execute("DELETE FROM messages_raw", NO_PARAMS)
+            .is_err());
+    }
+
     #[test]
     fn test_db_integrity_ok() {
         let filename = "/tmp/test_integrity.sqlite3";
@@ -438,4 +1115,1560 @@ mod test {
             Err(_) => assert!(true),
         }
     }
+
+    #[test]
+    fn test_db_integrity_fails_and_recovers() {
+        let filename = "/tmp/test_integrity_recover.sqlite3";
+        let keys = Vec::new();
+        std::fs::remove_file(filename.clone())
+            .or::<Result<()>>(Ok(()))
+            .unwrap();
+
+        let mut view = FlumeViewSql::new(filename.clone(), keys, "").unwrap();
+
+        std::fs::write(filename, b"BANG").unwrap();
+
+        let event = view.check_and_recover().unwrap().unwrap();
+        assert_eq!(event.reason, RebuildReason::FailedIntegrityCheck);
+
+        let moved_to = event.moved_to.unwrap();
+        assert_eq!(std::fs::read(&moved_to).unwrap(), b"BANG");
+        std::fs::remove_file(moved_to).unwrap();
+
+        view.check_db_integrity().unwrap();
+        assert_eq!(view.get_latest().unwrap(), 0);
+        assert!(view.check_and_recover().unwrap().is_none());
+    }
+
+    #[test]
+    fn unreadable_db_is_only_moved_aside_when_checked() {
+        let filename = "/tmp/test_integrity_unreadable.sqlite3";
+        std::fs::write(filename, b"BANG").unwrap();
+
+        let view = FlumeViewSql::new(filename, Vec::new(), "").unwrap();
+
+        let event = view.last_rebuild().unwrap();
+        assert_eq!(event.reason, RebuildReason::OutOfDate);
+        assert_eq!(event.moved_to, None);
+        assert_eq!(view.get_latest().unwrap(), 0);
+
+        std::fs::write(filename, b"BANG").unwrap();
+        let options = FlumeViewSqlOptions {
+            check_integrity_on_open: true,
+            ..FlumeViewSqlOptions::default()
+        };
+        let view = FlumeViewSql::new_with_options(filename, Vec::new(), "", options).unwrap();
+
+        let event = view.last_rebuild().unwrap();
+        assert_eq!(event.reason, RebuildReason::FailedIntegrityCheck);
+        std::fs::remove_file(event.moved_to.clone().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn baseline_db_is_rebuilt_as_out_of_date() {
+        let filename = "/tmp/test_baseline_schema.sqlite3";
+        std::fs::remove_file(filename.clone())
+            .or::<Result<()>>(Ok(()))
+            .unwrap();
+
+        // The migrations table from before plugins had versions, which has no `name` column.
+        Connection::open(filename)
+            .unwrap()
+            .execute_batch(
+                "
+                CREATE TABLE migrations (id INTEGER PRIMARY KEY, version INTEGER);
+                INSERT INTO migrations (id, version) VALUES (0, 1);
+                ",
+            )
+            .unwrap();
+
+        let view = FlumeViewSql::new(filename, Vec::new(), "").unwrap();
+
+        let event = view.last_rebuild().unwrap();
+        assert_eq!(event.reason, RebuildReason::OutOfDate);
+        assert_eq!(event.moved_to, None);
+        assert_eq!(view.get_latest().unwrap(), 0);
+
+        let options = FlumeViewSqlOptions {
+            check_integrity_on_open: true,
+            ..FlumeViewSqlOptions::default()
+        };
+        let view = FlumeViewSql::new_with_options(filename, Vec::new(), "", options).unwrap();
+        assert!(view.last_rebuild().is_none());
+    }
+
+    #[test]
+    fn export() {
+        let filename = "/tmp/test_export.sqlite3";
+        let keys = Vec::new();
+        std::fs::remove_file(filename.clone())
+            .or::<Result<()>>(Ok(()))
+            .unwrap();
+
+        let mut view = FlumeViewSql::new(filename, keys, "").unwrap();
+        let jsn = r#####"{
+  "key": "%KKPLj1tWfuVhCvgJz2hG/nIsVzmBRzUJaqHv+sb+n1c=.sha256",
+  "value": {
+    "author": "@QlCTpvY7p9ty2yOFrv1WU1AE88aoQc4Y7wYal7PFc+w=.ed25519",
+    "sequence": 4797,
+    "timestamp": 1543958997985,
+    "content": {
+      "type": "post",
+      "root": "%9EdpeKC5CgzpQs/x99CcnbD3n6ugUlwm19F7ZTqMh5w=.sha256",
+      "text": "hello, \"world\""
+    }
+  },
+  "timestamp": 1543959001933
+}
+"#####;
+        view.append(1234, jsn.as_bytes());
+
+        let reader = view.reader().unwrap();
+
+        let mut ndjson = Vec::new();
+        let filter = ExportFilter {
+            content_type: Some("post".to_string()),
+            min_seq: Some(1234),
+            ..Default::default()
+        };
+        let count = reader
+            .export(
+                ExportTable::Messages,
+                &filter,
+                ExportFormat::Ndjson,
+                &mut ndjson,
+            )
+            .unwrap();
+        assert_eq!(count, 1);
+
+        let row: Value = from_slice(&ndjson).unwrap();
+        assert_eq!(row["flume_seq"], 1234);
+        assert_eq!(row["content"]["text"], "hello, \"world\"");
+
+        let mut csv = Vec::new();
+        let filter = ExportFilter {
+            max_seq: Some(1000),
+            ..Default::default()
+        };
+        let count = reader
+            .export(ExportTable::Links, &filter, ExportFormat::Csv, &mut csv)
+            .unwrap();
+        assert_eq!(count, 0);
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "flume_seq,link_from_key,link_to_key,author,content_type\r\n"
+        );
+
+        assert!(reader
+            .export(
+                ExportTable::Votes,
+                &filter,
+                ExportFormat::Csv,
+                &mut Vec::new()
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn export_offset_log_round_trips() {
+        let filename = "/tmp/test_export_log.sqlite3";
+        let log_path = "/tmp/test_export_log.offset";
+        let keys = Vec::new();
+        std::fs::remove_file(filename.clone())
+            .or::<Result<()>>(Ok(()))
+            .unwrap();
+        std::fs::remove_file(log_path.clone())
+            .or::<Result<()>>(Ok(()))
+            .unwrap();
+
+        let mut view = FlumeViewSql::new(filename, keys, "").unwrap();
+        let jsn = r#####"{"key":"%KKPLj1tWfuVhCvgJz2hG/nIsVzmBRzUJaqHv+sb+n1c=.sha256","value":{"previous":null,"author":"@QlCTpvY7p9ty2yOFrv1WU1AE88aoQc4Y7wYal7PFc+w=.ed25519","sequence":1,"timestamp":1543958997985,"hash":"sha256","content":{"type":"post","text":"hello"},"signature":"mi5j/buYZdsiH8l6CVWRqdBKe+0UG6tVTOoVVjMhYl38Nkmb8wiIEfe7zu0JWuiHkaAIq+0/ZqYr6aV14j4fAw==.sig.ed25519"},"timestamp":1543959001933}"#####;
+        view.append(1234, jsn.as_bytes());
+
+        let reader = view.reader().unwrap();
+        let count = reader
+            .export_offset_log(&ExportFilter::default(), log_path)
+            .unwrap();
+        assert_eq!(count, 1);
+
+        let file = std::fs::File::open(log_path).unwrap();
+        let entries: Vec<_> = OffsetLogIter::<u32>::with_starting_offset(file, 0).collect();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].data, jsn.as_bytes());
+
+        assert!(reader
+            .export_offset_log(&ExportFilter::default(), log_path)
+            .is_err());
+    }
+
+    #[test]
+    fn feed_integrity() {
+        let filename = "/tmp/test_feed_integrity.sqlite3";
+        let keys = Vec::new();
+        std::fs::remove_file(filename.clone())
+            .or::<Result<()>>(Ok(()))
+            .unwrap();
+
+        let mut view = FlumeViewSql::new(filename, keys, "").unwrap();
+        let author = "@QlCTpvY7p9ty2yOFrv1WU1AE88aoQc4Y7wYal7PFc+w=.ed25519";
+        let messages = [
+            (1, "%one", Value::Null),
+            (2, "%two", json!("%not-one")),
+            (3, "%three", json!("%two")),
+            (5, "%five", json!("%four")),
+        ];
+
+        for (seq, key, previous) in messages.iter() {
+            let item = json!({
+                "key": key,
+                "value": {
+                    "previous": previous,
+                    "author": author,
+                    "sequence": seq,
+                    "timestamp": 1543958997985.0,
+                    "hash": "sha256",
+                    "content": {"type": "post"},
+                    "signature": "sig"
+                },
+                "timestamp": 1543959001933.0
+            });
+            view.append(*seq as u64 * 100, &to_vec(&item).unwrap());
+        }
+
+        let integrity = view.reader().unwrap().feed_integrity(author).unwrap();
+
+        assert_eq!(integrity.message_count, 4);
+        assert_eq!(integrity.latest_seq, Some(5));
+        assert_eq!(integrity.seq_gaps, vec![queries::SeqGap { from: 4, to: 4 }]);
+        assert_eq!(integrity.chain_breaks.len(), 1);
+        assert_eq!(integrity.chain_breaks[0].key, "%two");
+        assert_eq!(integrity.chain_breaks[0].expected_previous, vec!["%one"]);
+    }
+
+    #[test]
+    fn validate_messages() {
+        let filename = "/tmp/test_validate.sqlite3";
+        let keys = Vec::new();
+        std::fs::remove_file(filename.clone())
+            .or::<Result<()>>(Ok(()))
+            .unwrap();
+
+        let options = FlumeViewSqlOptions {
+            validate_messages: true,
+            ..FlumeViewSqlOptions::default()
+        };
+        let mut view = FlumeViewSql::new_with_options(filename, keys, "", options).unwrap();
+
+        let valid = r#####"{"key":"%+TwpK+iaewOwLWLIHVKCwuA+0TxKtVIkiAcoQMjDeLA=.sha256","value":{"previous":null,"author":"@WgPLht0XZQBilc8s5njIl3ywszClfrtaQ8+9IGwZTzI=.ed25519","sequence":1,"timestamp":1543958997985,"hash":"sha256","content":{"type":"post","text":"héllo validation"},"signature":"lZc1s6FQsa8WxmkDSM2I4wW/1trpPwvPDlbKYztBEBqdK3EMRoCngQH6BBphpVM2poDueRHaOSV0LKSz6qfhBA==.sig.ed25519"},"timestamp":1543959001933}"#####;
+        let tampered = valid
+            .replace("héllo validation", "tampered validation")
+            .replace("%+TwpK", "%-TwpK");
+
+        let value: Value = from_str(valid).unwrap();
+        assert_eq!(
+            validate_message(value["key"].as_str().unwrap(), &value["value"]),
+            Ok(())
+        );
+
+        view.append(100, valid.as_bytes());
+        view.append(200, tampered.as_bytes());
+
+        let reader = view.reader().unwrap();
+        let connection = reader.connection().unwrap();
+        let is_valid: Vec<Option<bool>> = connection
+            .prepare("SELECT is_valid FROM messages_raw ORDER BY flume_seq")
+            .unwrap()
+            .query_map(NO_PARAMS, |row| row.get(0))
+            .unwrap()
+            .map(|row| row.unwrap())
+            .collect();
+        assert_eq!(is_valid, vec![Some(true), Some(false)]);
+
+        assert_eq!(reader.search("validation", 10).unwrap().len(), 1);
+
+        // Text indexed before a message was found to be invalid isn't searched.
+        view.connection
+            .execute("UPDATE messages_raw SET is_valid = 0", NO_PARAMS)
+            .unwrap();
+        assert!(reader.search("validation", 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn feed_forks() {
+        let filename = "/tmp/test_feed_forks.sqlite3";
+        let keys = Vec::new();
+        std::fs::remove_file(filename.clone())
+            .or::<Result<()>>(Ok(()))
+            .unwrap();
+
+        let mut view = FlumeViewSql::new(filename, keys, "").unwrap();
+        let author = "@QlCTpvY7p9ty2yOFrv1WU1AE88aoQc4Y7wYal7PFc+w=.ed25519";
+
+        for (flume_seq, (seq, key)) in [(1, "%one"), (2, "%two"), (2, "%other-two")]
+            .iter()
+            .enumerate()
+        {
+            let item = json!({
+                "key": key,
+                "value": {
+                    "author": author,
+                    "sequence": seq,
+                    "timestamp": 1543958997985.0,
+                    "content": {"type": "post"}
+                },
+                "timestamp": 1543959001933.0
+            });
+            view.append(flume_seq as u64 * 100, &to_vec(&item).unwrap());
+        }
+
+        let forks = view.reader().unwrap().forked_feeds().unwrap();
+
+        assert_eq!(forks.len(), 1);
+        assert_eq!(forks[0].author, author);
+        assert_eq!(forks[0].seq, 2);
+        assert_eq!(forks[0].keys, vec!["%two", "%other-two"]);
+    }
+
+    #[test]
+    fn feed_tips() {
+        let filename = "/tmp/test_feed_tips.sqlite3";
+        let keys = Vec::new();
+        std::fs::remove_file(filename.clone())
+            .or::<Result<()>>(Ok(()))
+            .unwrap();
+
+        let mut view = FlumeViewSql::new(filename, keys, "").unwrap();
+        let author = "@QlCTpvY7p9ty2yOFrv1WU1AE88aoQc4Y7wYal7PFc+w=.ed25519";
+
+        for (flume_seq, (seq, key, received)) in [
+            (2, "%two", 2000.0),
+            (3, "%three", 3000.0),
+            (1, "%one", 4000.0),
+        ]
+        .iter()
+        .enumerate()
+        {
+            let item = json!({
+                "key": key,
+                "value": {
+                    "author": author,
+                    "sequence": seq,
+                    "timestamp": 1000.0,
+                    "content": {"type": "post"}
+                },
+                "timestamp": received
+            });
+            view.append(flume_seq as u64 * 100, &to_vec(&item).unwrap());
+        }
+
+        let tips = view.reader().unwrap().feed_tips(None).unwrap();
+
+        assert_eq!(tips.len(), 1);
+        assert_eq!(tips[0].author, author);
+        assert_eq!(tips[0].latest_seq, 3);
+        assert_eq!(tips[0].latest_key, "%three");
+        assert_eq!(tips[0].message_count, 3);
+        assert_eq!(tips[0].first_seen, 2000.0);
+        assert_eq!(tips[0].last_seen, 4000.0);
+
+        assert!(view
+            .reader()
+            .unwrap()
+            .feed_tips(Some(5000.0))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn git_repos() {
+        let filename = "/tmp/test_git_repos.sqlite3";
+        let keys = Vec::new();
+        std::fs::remove_file(filename.clone())
+            .or::<Result<()>>(Ok(()))
+            .unwrap();
+
+        let mut view = FlumeViewSql::new(filename, keys, "").unwrap();
+        let alice = "@QlCTpvY7p9ty2yOFrv1WU1AE88aoQc4Y7wYal7PFc+w=.ed25519";
+        let bob = "@U5GvOKP/YUza9k53DSXxT0mk3PIrnyAmessvNfZl5E0=.ed25519";
+        let carol = "@Y5v8KS6nIj/w2UlFGi3m5lGtIKdiXdPRfnS4fs3QQx8=.ed25519";
+
+        let messages = [
+            (
+                alice,
+                "%repo",
+                json!({"type": "git-repo", "name": "ssb-sql"}),
+            ),
+            (
+                alice,
+                "%update-one",
+                json!({
+                    "type": "git-update",
+                    "repo": "%repo",
+                    "refs": {"refs/heads/master": "aaa", "refs/heads/wip": "bbb"},
+                    "packs": [{"link": "&pack.sha256"}],
+                    "indexes": [{"link": "&index.sha256"}]
+                }),
+            ),
+            (
+                bob,
+                "%issue-one",
+                json!({"type": "issue", "project": "%repo", "text": "Crashes\nwhen empty"}),
+            ),
+            (
+                bob,
+                "%issue-two",
+                json!({"type": "issue", "project": "%repo", "title": "Slow"}),
+            ),
+            (
+                bob,
+                "%pr",
+                json!({"type": "pull-request", "project": "%repo", "title": "Speed up"}),
+            ),
+            (
+                alice,
+                "%update-two",
+                json!({
+                    "type": "git-update",
+                    "repo": "%repo",
+                    "refs": {"refs/heads/master": "ccc", "refs/heads/wip": null}
+                }),
+            ),
+            (
+                carol,
+                "%edit-one",
+                json!({"type": "issue-edit", "issues": [{"link": "%issue-one", "open": false}]}),
+            ),
+            (
+                alice,
+                "%edit-two",
+                json!({"type": "issue-edit", "issues": [{"link": "%issue-two", "open": false}]}),
+            ),
+            (
+                alice,
+                "%rename",
+                json!({"type": "about", "about": "%repo", "name": "ssb-flumeview-sql"}),
+            ),
+        ];
+
+        for (flume_seq, (author, key, content)) in messages.iter().enumerate() {
+            let item = json!({
+                "key": key,
+                "value": {
+                    "author": author,
+                    "sequence": flume_seq + 1,
+                    "timestamp": 1000.0 + flume_seq as f64,
+                    "content": content
+                },
+                "timestamp": 1000.0 + flume_seq as f64
+            });
+            view.append(flume_seq as u64 * 100, &to_vec(&item).unwrap());
+        }
+
+        let reader = view.reader().unwrap();
+
+        let repos = reader.repos_by_author(alice).unwrap();
+        assert_eq!(repos.len(), 1);
+        assert_eq!(repos[0].key, "%repo");
+        assert_eq!(repos[0].name, Some("ssb-flumeview-sql".to_string()));
+        assert!(reader.repos_by_author(bob).unwrap().is_empty());
+
+        let refs = reader.repo_refs("%repo").unwrap();
+        assert_eq!(refs.len(), 1);
+        assert_eq!(refs[0].name, "refs/heads/master");
+        assert_eq!(refs[0].sha, "ccc");
+        assert_eq!(refs[0].update_key, "%update-two");
+
+        let issues = reader.open_issues("%repo").unwrap();
+        let issue_keys: Vec<&str> = issues.iter().map(|issue| issue.key.as_str()).collect();
+        assert_eq!(issue_keys, vec!["%pr", "%issue-one"]);
+        assert_eq!(issues[0].kind, "pull-request");
+        assert_eq!(issues[1].title, Some("Crashes".to_string()));
+
+        let connection = reader.connection().unwrap();
+        let packs: Vec<String> = connection
+            .prepare("SELECT blob FROM git_packs ORDER BY blob")
+            .unwrap()
+            .query_map(NO_PARAMS, |row| row.get(0))
+            .unwrap()
+            .map(|blob| blob.unwrap())
+            .collect();
+        assert_eq!(packs, vec!["&index.sha256", "&pack.sha256"]);
+    }
+
+    #[test]
+    fn gatherings() {
+        let filename = "/tmp/test_gatherings.sqlite3";
+        let keys = Vec::new();
+        std::fs::remove_file(filename.clone())
+            .or::<Result<()>>(Ok(()))
+            .unwrap();
+
+        let mut view = FlumeViewSql::new(filename, keys, "").unwrap();
+        let alice = "@QlCTpvY7p9ty2yOFrv1WU1AE88aoQc4Y7wYal7PFc+w=.ed25519";
+        let bob = "@U5GvOKP/YUza9k53DSXxT0mk3PIrnyAmessvNfZl5E0=.ed25519";
+
+        let messages = [
+            (alice, "%party", json!({"type": "gathering"})),
+            (alice, "%past", json!({"type": "gathering"})),
+            (
+                alice,
+                "%party-about",
+                json!({
+                    "type": "about",
+                    "about": "%party",
+                    "title": "Party",
+                    "startDateTime": {"epoch": 5000.0, "tz": "utc"}
+                }),
+            ),
+            (
+                alice,
+                "%past-about",
+                json!({"type": "about", "about": "%past", "startDateTime": {"epoch": 10.0}}),
+            ),
+            (
+                bob,
+                "%bob-rename",
+                json!({"type": "about", "about": "%party", "title": "Bob's party"}),
+            ),
+            (
+                alice,
+                "%party-location",
+                json!({"type": "about", "about": "%party", "location": "Beach"}),
+            ),
+            (
+                alice,
+                "%alice-attends",
+                json!({"type": "about", "about": "%party", "attendee": {"link": alice}}),
+            ),
+            (
+                bob,
+                "%bob-attends",
+                json!({"type": "about", "about": "%party", "attendee": {"link": bob}}),
+            ),
+            (
+                bob,
+                "%bob-attends-alice",
+                json!({"type": "about", "about": "%party", "attendee": {"link": alice, "remove": true}}),
+            ),
+            (
+                bob,
+                "%bob-leaves",
+                json!({"type": "about", "about": "%party", "attendee": {"link": bob, "remove": true}}),
+            ),
+        ];
+
+        for (flume_seq, (author, key, content)) in messages.iter().enumerate() {
+            let item = json!({
+                "key": key,
+                "value": {
+                    "author": author,
+                    "sequence": flume_seq + 1,
+                    "timestamp": 1000.0 + flume_seq as f64,
+                    "content": content
+                },
+                "timestamp": 1000.0 + flume_seq as f64
+            });
+            view.append(flume_seq as u64 * 100, &to_vec(&item).unwrap());
+        }
+
+        let reader = view.reader().unwrap();
+
+        let gatherings = reader.upcoming_gatherings(1000.0, 10).unwrap();
+        assert_eq!(gatherings.len(), 1);
+        assert_eq!(gatherings[0].key, "%party");
+        assert_eq!(gatherings[0].title, Some("Party".to_string()));
+        assert_eq!(gatherings[0].location, Some("Beach".to_string()));
+        assert_eq!(gatherings[0].start_time, Some(5000.0));
+        assert_eq!(gatherings[0].attendee_count, 1);
+
+        assert_eq!(reader.gathering_attendees("%party").unwrap(), vec![alice]);
+    }
+
+    #[test]
+    fn ranked_pubs() {
+        let filename = "/tmp/test_ranked_pubs.sqlite3";
+        let keys = Vec::new();
+        std::fs::remove_file(filename.clone())
+            .or::<Result<()>>(Ok(()))
+            .unwrap();
+
+        let me = "@QlCTpvY7p9ty2yOFrv1WU1AE88aoQc4Y7wYal7PFc+w=.ed25519";
+        let alice = "@U5GvOKP/YUza9k53DSXxT0mk3PIrnyAmessvNfZl5E0=.ed25519";
+        let bob = "@Y5v8KS6nIj/w2UlFGi3m5lGtIKdiXdPRfnS4fs3QQx8=.ed25519";
+        let carol = "@EMovhfIrFk4NihAKnRNhrfRaqIhBv1Wj8pTxJNgvCCY=.ed25519";
+        let one = "@7xMrWP8708+LDvaJrRMRQJEixWYp4Oipa9ohqY7+NyQ=.ed25519";
+        let two = "@gYCJpN4eGDjHFnWW2Fcusj8O4QYbVDUW6rNYh7nNEnc=.ed25519";
+
+        let mut view = FlumeViewSql::new(filename, keys, me).unwrap();
+
+        let messages = [
+            (
+                me,
+                json!({"type": "contact", "contact": alice, "following": true}),
+            ),
+            (
+                me,
+                json!({"type": "contact", "contact": bob, "following": true}),
+            ),
+            (
+                alice,
+                json!({"type": "pub", "address": {"host": "one.example.com", "port": 8008, "key": one}}),
+            ),
+            (
+                carol,
+                json!({"type": "pub", "address": "net:two.example.com:8008~shs:gYCJpN4eGDjHFnWW2Fcusj8O4QYbVDUW6rNYh7nNEnc="}),
+            ),
+            (
+                alice,
+                json!({"type": "pub", "address": {"host": "two.example.com", "port": 8008, "key": two}}),
+            ),
+            (
+                bob,
+                json!({"type": "contact", "contact": two, "following": true}),
+            ),
+            (
+                carol,
+                json!({"type": "pub", "address": {"host": "three.example.com", "port": 8008, "key": carol}}),
+            ),
+        ];
+
+        for (flume_seq, (author, content)) in messages.iter().enumerate() {
+            let item = json!({
+                "key": format!("%message-{}", flume_seq),
+                "value": {
+                    "author": author,
+                    "sequence": flume_seq + 1,
+                    "timestamp": 1000.0 + flume_seq as f64,
+                    "content": content
+                },
+                "timestamp": 1000.0 + flume_seq as f64
+            });
+            view.append(flume_seq as u64 * 100, &to_vec(&item).unwrap());
+        }
+
+        let pubs = view.reader().unwrap().ranked_pubs(me).unwrap();
+        let ranking: Vec<(&str, i64)> = pubs
+            .iter()
+            .map(|ranked| (ranked.host.as_str(), ranked.friend_count))
+            .collect();
+
+        assert_eq!(
+            ranking,
+            vec![
+                ("two.example.com", 2),
+                ("one.example.com", 1),
+                ("three.example.com", 0)
+            ]
+        );
+        assert_eq!(pubs[0].key, two);
+        assert_eq!(pubs[0].port, 8008);
+    }
+
+    #[test]
+    fn tags() {
+        let filename = "/tmp/test_tags.sqlite3";
+        let keys = Vec::new();
+        std::fs::remove_file(filename.clone())
+            .or::<Result<()>>(Ok(()))
+            .unwrap();
+
+        let me = "@QlCTpvY7p9ty2yOFrv1WU1AE88aoQc4Y7wYal7PFc+w=.ed25519";
+        let alice = "@U5GvOKP/YUza9k53DSXxT0mk3PIrnyAmessvNfZl5E0=.ed25519";
+
+        let mut view = FlumeViewSql::new(filename, keys, me).unwrap();
+
+        let messages = [
+            (alice, "%post-one", json!({"type": "post", "text": "one"})),
+            (alice, "%post-two", json!({"type": "post", "text": "two"})),
+            (
+                me,
+                "%bookmarks",
+                json!({"type": "tag", "name": "bookmarks"}),
+            ),
+            (
+                alice,
+                "%alices-tag",
+                json!({"type": "tag", "name": "reading"}),
+            ),
+            (
+                me,
+                "%tag-one",
+                json!({"type": "tag", "root": "%bookmarks", "branch": "%bookmarks", "message": "%post-one", "tagged": true}),
+            ),
+            (
+                me,
+                "%tag-two",
+                json!({"type": "tag", "root": "%bookmarks", "branch": "%tag-one", "message": "%post-two", "tagged": true}),
+            ),
+            (
+                alice,
+                "%alice-tag-two",
+                json!({"type": "tag", "root": "%alices-tag", "branch": "%alices-tag", "message": "%post-two", "tagged": true}),
+            ),
+            (
+                me,
+                "%untag-one",
+                json!({"type": "tag", "root": "%bookmarks", "branch": "%tag-two", "message": "%post-one", "tagged": false}),
+            ),
+        ];
+
+        for (flume_seq, (author, key, content)) in messages.iter().enumerate() {
+            let item = json!({
+                "key": key,
+                "value": {
+                    "author": author,
+                    "sequence": flume_seq + 1,
+                    "timestamp": 1000.0 + flume_seq as f64,
+                    "content": content
+                },
+                "timestamp": 1000.0 + flume_seq as f64
+            });
+            view.append(flume_seq as u64 * 100, &to_vec(&item).unwrap());
+        }
+
+        let reader = view.reader().unwrap();
+
+        let tags = reader.my_tags().unwrap();
+        assert_eq!(tags.len(), 1);
+        assert_eq!(tags[0].key, "%bookmarks");
+        assert_eq!(tags[0].name, "bookmarks");
+        assert_eq!(tags[0].message_count, 1);
+
+        let tagged = reader.messages_with_tag("%bookmarks").unwrap();
+        assert_eq!(tagged.len(), 1);
+        assert_eq!(tagged[0].key, "%post-two");
+
+        let names: Vec<String> = reader
+            .tags_on_message("%post-two")
+            .unwrap()
+            .into_iter()
+            .map(|tag| tag.name)
+            .collect();
+        assert_eq!(names, vec!["bookmarks", "reading"]);
+        assert!(reader.tags_on_message("%post-one").unwrap().is_empty());
+    }
+
+    #[test]
+    fn blob_metadata() {
+        let filename = "/tmp/test_blob_metadata.sqlite3";
+        let keys = Vec::new();
+        std::fs::remove_file(filename.clone())
+            .or::<Result<()>>(Ok(()))
+            .unwrap();
+
+        let me = "@QlCTpvY7p9ty2yOFrv1WU1AE88aoQc4Y7wYal7PFc+w=.ed25519";
+        let alice = "@U5GvOKP/YUza9k53DSXxT0mk3PIrnyAmessvNfZl5E0=.ed25519";
+        let bob = "@Y5v8KS6nIj/w2UlFGi3m5lGtIKdiXdPRfnS4fs3QQx8=.ed25519";
+        let carol = "@EMovhfIrFk4NihAKnRNhrfRaqIhBv1Wj8pTxJNgvCCY=.ed25519";
+
+        let mut view = FlumeViewSql::new(filename, keys, me).unwrap();
+
+        let messages = [
+            (
+                me,
+                json!({"type": "contact", "contact": alice, "following": true}),
+            ),
+            (
+                alice,
+                json!({"type": "contact", "contact": bob, "following": true}),
+            ),
+            (
+                alice,
+                json!({
+                    "type": "post",
+                    "text": "look",
+                    "mentions": [{"link": "&a.sha256", "type": "image/png", "size": 100, "name": "a.png"}]
+                }),
+            ),
+            (
+                bob,
+                json!({
+                    "type": "about",
+                    "about": bob,
+                    "image": {"link": "&b.sha256", "width": 64, "height": 32}
+                }),
+            ),
+            (
+                carol,
+                json!({"type": "post", "text": "&c.sha256", "mentions": [{"link": "&c.sha256"}]}),
+            ),
+            (
+                me,
+                json!({"type": "post", "text": "mine", "mentions": [{"link": "&d.sha256", "size": 5}]}),
+            ),
+            (
+                carol,
+                json!({"type": "post", "text": "again", "mentions": [{"link": "&a.sha256", "name": "other.png"}]}),
+            ),
+        ];
+
+        for (flume_seq, (author, content)) in messages.iter().enumerate() {
+            let item = json!({
+                "key": format!("%message-{}", flume_seq),
+                "value": {
+                    "author": author,
+                    "sequence": flume_seq + 1,
+                    "timestamp": 1000.0 + flume_seq as f64,
+                    "content": content
+                },
+                "timestamp": 1000.0 + flume_seq as f64
+            });
+            view.append(flume_seq as u64 * 100, &to_vec(&item).unwrap());
+        }
+
+        let reader = view.reader().unwrap();
+
+        let one_hop = reader.blobs_within_hops(me, 1).unwrap();
+        let one_hop_ids: Vec<&str> = one_hop.iter().map(|blob| blob.blob.as_str()).collect();
+        assert_eq!(one_hop_ids, vec!["&a.sha256", "&d.sha256"]);
+        assert_eq!(one_hop[0].mime_type, Some("image/png".to_string()));
+        assert_eq!(one_hop[0].name, Some("a.png".to_string()));
+        assert_eq!(one_hop[0].size, Some(100));
+
+        let two_hops = reader.blobs_within_hops(me, 2).unwrap();
+        assert_eq!(two_hops.len(), 3);
+        assert_eq!(two_hops[1].blob, "&b.sha256");
+        assert_eq!(two_hops[1].width, Some(64));
+        assert_eq!(two_hops[1].height, Some(32));
+
+        let mine = reader.my_blobs().unwrap();
+        assert_eq!(mine.len(), 1);
+        assert_eq!(mine[0].blob, "&d.sha256");
+        assert_eq!(mine[0].size, Some(5));
+    }
+
+    #[test]
+    fn blob_report() {
+        let filename = "/tmp/test_blob_report.sqlite3";
+        let blobs_path = "/tmp/test_blob_report_blobs";
+        let keys = Vec::new();
+        std::fs::remove_file(filename.clone())
+            .or::<Result<()>>(Ok(()))
+            .unwrap();
+        std::fs::remove_dir_all(blobs_path)
+            .or::<Result<()>>(Ok(()))
+            .unwrap();
+
+        let zeros = "&AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=.sha256";
+        let ones = "&//////////////////////////////////////////8=.sha256";
+        let missing = "&Y2QBY5DLZUlzIm5AC1Eq9QOhs0J5rJq69SEKJxkU15I=.sha256";
+
+        for (dir, file) in [("00", "00".repeat(31)), ("ff", "ff".repeat(31))].iter() {
+            let dir = std::path::Path::new(blobs_path).join("sha256").join(dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join(file), b"blob").unwrap();
+        }
+
+        let local_blobs = scan_blob_store(blobs_path).unwrap();
+        assert_eq!(local_blobs, vec![ones, zeros]);
+
+        let me = "@QlCTpvY7p9ty2yOFrv1WU1AE88aoQc4Y7wYal7PFc+w=.ed25519";
+        let mut view = FlumeViewSql::new(filename, keys, me).unwrap();
+
+        let item = json!({
+            "key": "%post",
+            "value": {
+                "author": me,
+                "sequence": 1,
+                "timestamp": 1000.0,
+                "content": {"type": "post", "text": "hi", "mentions": [{"link": zeros}, {"link": missing}]}
+            },
+            "timestamp": 1000.0
+        });
+        view.append(0, &to_vec(&item).unwrap());
+
+        let report = view
+            .reader()
+            .unwrap()
+            .blob_report(me, 1, &local_blobs)
+            .unwrap();
+
+        assert_eq!(report.orphaned, vec![ones]);
+        assert_eq!(report.missing, vec![missing]);
+    }
+
+    #[test]
+    fn hidden_content() {
+        let filename = "/tmp/test_hidden_content.sqlite3";
+        let keys = Vec::new();
+        std::fs::remove_file(filename.clone())
+            .or::<Result<()>>(Ok(()))
+            .unwrap();
+
+        let mut view = FlumeViewSql::new(filename, keys, "").unwrap();
+        let alice = "@QlCTpvY7p9ty2yOFrv1WU1AE88aoQc4Y7wYal7PFc+w=.ed25519";
+        let bob = "@U5GvOKP/YUza9k53DSXxT0mk3PIrnyAmessvNfZl5E0=.ed25519";
+
+        let alice_item = to_vec(&json!({
+            "key": "%alice-spam",
+            "value": {
+                "author": alice,
+                "sequence": 1,
+                "timestamp": 1000.0,
+                "content": {"type": "post", "text": "spam", "mentions": [{"link": "%bob-blob"}]}
+            },
+            "timestamp": 1000.0
+        }))
+        .unwrap();
+        view.append(0, &alice_item);
+
+        let messages = [
+            (
+                "%bob-blob",
+                json!({"type": "post", "text": "look", "mentions": [{"link": "&bad.sha256"}]}),
+            ),
+            ("%bob-hidden", json!({"type": "post", "text": "oops"})),
+            (
+                "%bob-reply",
+                json!({"type": "post", "text": "hi", "root": "%alice-spam"}),
+            ),
+        ];
+
+        for (seq, (key, content)) in messages.iter().enumerate() {
+            let item = json!({
+                "key": key,
+                "value": {
+                    "author": bob,
+                    "sequence": seq + 1,
+                    "timestamp": 2000.0 + seq as f64,
+                    "content": content
+                },
+                "timestamp": 2000.0 + seq as f64
+            });
+            view.append((seq as u64 + 1) * 100, &to_vec(&item).unwrap());
+        }
+
+        let hidden = vec![
+            HiddenContent {
+                kind: HiddenKind::Author,
+                id: alice.to_string(),
+                purge: true,
+            },
+            HiddenContent {
+                kind: HiddenKind::Message,
+                id: "%bob-hidden".to_string(),
+                purge: false,
+            },
+            HiddenContent {
+                kind: HiddenKind::Blob,
+                id: "&bad.sha256".to_string(),
+                purge: false,
+            },
+        ];
+        for content in hidden.iter() {
+            view.hide(content).unwrap();
+        }
+
+        let reader = view.reader().unwrap();
+
+        let feed: Vec<String> = reader
+            .feed(bob, None, 10)
+            .unwrap()
+            .into_iter()
+            .map(|message| message.key)
+            .collect();
+        assert_eq!(feed, vec!["%bob-reply"]);
+        assert!(reader.feed(alice, None, 10).unwrap().is_empty());
+        assert!(reader.search("spam", 10).unwrap().is_empty());
+
+        let mut exported = Vec::new();
+        let num_rows = reader
+            .export(
+                ExportTable::Messages,
+                &ExportFilter::default(),
+                ExportFormat::Ndjson,
+                &mut exported,
+            )
+            .unwrap();
+        assert_eq!(num_rows, 1);
+        assert!(String::from_utf8(exported).unwrap().contains("%bob-reply"));
+
+        {
+            let connection = reader.connection().unwrap();
+            let count = |sql: &str| -> i64 {
+                connection
+                    .query_row(sql, NO_PARAMS, |row| row.get(0))
+                    .unwrap()
+            };
+            assert_eq!(count("SELECT COUNT(*) FROM links_raw"), 1);
+            assert_eq!(count("SELECT COUNT(*) FROM links"), 0);
+            assert_eq!(count("SELECT COUNT(*) FROM blob_links"), 0);
+            assert_eq!(
+                count("SELECT COUNT(*) FROM search_fts WHERE search_fts MATCH 'spam'"),
+                0
+            );
+            assert_eq!(
+                count("SELECT COUNT(*) FROM messages_raw WHERE content IS NULL AND raw IS NULL"),
+                1
+            );
+        }
+
+        view.unhide(HiddenKind::Message, "%bob-hidden").unwrap();
+        assert_eq!(reader.feed(bob, None, 10).unwrap().len(), 2);
+
+        drop(reader);
+        view.rebuild().unwrap();
+        assert_eq!(
+            view.reader().unwrap().hidden_content().unwrap(),
+            vec![hidden[0].clone(), hidden[2].clone()]
+        );
+
+        view.append(0, &alice_item);
+        let connection = view.reader().unwrap().connection().unwrap();
+        let content: Option<String> = connection
+            .query_row("SELECT content FROM messages_raw", NO_PARAMS, |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(content, None);
+    }
+
+    #[test]
+    fn exclude_blocked_feeds() {
+        let filename = "/tmp/test_exclude_blocked_feeds.sqlite3";
+        let keys = Vec::new();
+        std::fs::remove_file(filename.clone())
+            .or::<Result<()>>(Ok(()))
+            .unwrap();
+
+        let me = "@QlCTpvY7p9ty2yOFrv1WU1AE88aoQc4Y7wYal7PFc+w=.ed25519";
+        let bob = "@U5GvOKP/YUza9k53DSXxT0mk3PIrnyAmessvNfZl5E0=.ed25519";
+
+        let options = FlumeViewSqlOptions {
+            exclude_blocked_feeds: true,
+            plugins: vec![Box::new(PollsPlugin { version: 1 })],
+            ..FlumeViewSqlOptions::default()
+        };
+        let mut view = FlumeViewSql::new_with_options(filename, keys, me, options).unwrap();
+
+        let messages = [
+            (
+                "%bob-spam",
+                bob,
+                json!({"type": "post", "text": "spam", "mentions": [{"link": me}]}),
+            ),
+            ("%bob-poll", bob, json!({"type": "poll", "title": "Spam?"})),
+            (
+                "%me-block",
+                me,
+                json!({"type": "contact", "contact": bob, "blocking": true}),
+            ),
+            (
+                "%bob-more",
+                bob,
+                json!({"type": "post", "text": "more spam"}),
+            ),
+        ];
+
+        for (seq, (key, author, content)) in messages.iter().enumerate() {
+            let item = json!({
+                "key": key,
+                "value": {
+                    "author": author,
+                    "sequence": seq + 1,
+                    "timestamp": 1000.0 + seq as f64,
+                    "content": content
+                },
+                "timestamp": 1000.0 + seq as f64
+            });
+            view.append((seq as u64 + 1) * 100, &to_vec(&item).unwrap());
+        }
+
+        // These plugins can't remove a single feed, so they were rebuilt.
+        assert_eq!(
+            view.lagging_plugins(),
+            &["git", "gatherings", "tags", "polls"]
+        );
+        while view.catch_up_plugins(100).unwrap() > 0 {}
+
+        let count = |view: &FlumeViewSql, sql: &str| -> i64 {
+            view.reader()
+                .unwrap()
+                .connection()
+                .unwrap()
+                .query_row(sql, NO_PARAMS, |row| row.get(0))
+                .unwrap()
+        };
+
+        let reader = view.reader().unwrap();
+        assert!(reader.feed(bob, None, 10).unwrap().is_empty());
+        assert!(reader.search("spam", 10).unwrap().is_empty());
+        assert_eq!(count(&view, "SELECT COUNT(*) FROM messages_raw"), 4);
+        assert_eq!(count(&view, "SELECT COUNT(*) FROM mentions_raw"), 0);
+        assert_eq!(count(&view, "SELECT COUNT(*) FROM feeds"), 1);
+        assert_eq!(count(&view, "SELECT COUNT(*) FROM polls"), 0);
+
+        let item = json!({
+            "key": "%me-unblock",
+            "value": {
+                "author": me,
+                "sequence": 5,
+                "timestamp": 1005.0,
+                "content": {"type": "contact", "contact": bob, "blocking": false}
+            },
+            "timestamp": 1005.0
+        });
+        view.append(500, &to_vec(&item).unwrap());
+        while view.catch_up_plugins(100).unwrap() > 0 {}
+
+        assert_eq!(reader.feed(bob, None, 10).unwrap().len(), 3);
+        assert_eq!(reader.search("spam", 10).unwrap().len(), 2);
+        assert_eq!(count(&view, "SELECT COUNT(*) FROM mentions_raw"), 1);
+        assert_eq!(count(&view, "SELECT COUNT(*) FROM feeds"), 2);
+        assert_eq!(count(&view, "SELECT COUNT(*) FROM polls"), 1);
+    }
+
+    #[test]
+    fn feed_pages() {
+        let filename = "/tmp/test_feed_pages.sqlite3";
+        let keys = Vec::new();
+        std::fs::remove_file(filename.clone())
+            .or::<Result<()>>(Ok(()))
+            .unwrap();
+
+        let mut view = FlumeViewSql::new(filename, keys, "").unwrap();
+        let alice = "@QlCTpvY7p9ty2yOFrv1WU1AE88aoQc4Y7wYal7PFc+w=.ed25519";
+        let bob = "@U5GvOKP/YUza9k53DSXxT0mk3PIrnyAmessvNfZl5E0=.ed25519";
+
+        // Received in this order, but asserted_time puts %d first and ties %b and %c, which are then
+        // ordered by flume_seq.
+        let messages = [
+            ("%a", alice, 3000.0, json!({"type": "post", "text": "a"})),
+            ("%b", bob, 2000.0, json!({"type": "vote"})),
+            (
+                "%c",
+                bob,
+                2000.0,
+                json!({"type": "post", "text": "c", "mentions": [{"link": alice}]}),
+            ),
+            ("%d", alice, 4000.0, json!({"type": "post", "text": "d"})),
+        ];
+
+        let append = |view: &mut FlumeViewSql,
+                      seq: usize,
+                      (key, author, time, content): (&str, &str, f64, Value)| {
+            let item = json!({
+                "key": key,
+                "value": {
+                    "author": author,
+                    "sequence": seq + 1,
+                    "timestamp": time,
+                    "content": content
+                },
+                "timestamp": 5000.0 + seq as f64
+            });
+            view.append(seq as u64 * 100, &to_vec(&item).unwrap());
+        };
+        for (seq, message) in messages.iter().cloned().enumerate() {
+            append(&mut view, seq, message);
+        }
+
+        let reader = view.reader().unwrap();
+        let page_keys = |page: &FeedPage| -> Vec<String> {
+            page.messages
+                .iter()
+                .map(|message| message.key.clone())
+                .collect()
+        };
+
+        let first = reader
+            .public_feed(FeedOrder::FlumeSeq, None, None, 2)
+            .unwrap();
+        assert_eq!(page_keys(&first), vec!["%d", "%c"]);
+        let second = reader
+            .public_feed(
+                FeedOrder::FlumeSeq,
+                first.before.as_ref().map(|c| c.as_str()),
+                None,
+                2,
+            )
+            .unwrap();
+        assert_eq!(page_keys(&second), vec!["%b", "%a"]);
+        let third = reader
+            .public_feed(
+                FeedOrder::FlumeSeq,
+                second.before.as_ref().map(|c| c.as_str()),
+                None,
+                2,
+            )
+            .unwrap();
+        assert!(third.messages.is_empty());
+        assert_eq!(third.before, None);
+
+        // Cursors work with either order.
+        let by_time = reader
+            .public_feed(FeedOrder::AssertedTime, None, None, 10)
+            .unwrap();
+        assert_eq!(page_keys(&by_time), vec!["%d", "%a", "%c", "%b"]);
+        let past_end = reader
+            .public_feed(
+                FeedOrder::AssertedTime,
+                by_time.before.as_ref().map(|c| c.as_str()),
+                None,
+                10,
+            )
+            .unwrap();
+        assert!(past_end.messages.is_empty());
+        let older = reader
+            .public_feed(
+                FeedOrder::AssertedTime,
+                first.before.as_ref().map(|c| c.as_str()),
+                None,
+                10,
+            )
+            .unwrap();
+        assert_eq!(page_keys(&older), vec!["%b"]);
+
+        let newer = reader
+            .author_feed(
+                alice,
+                FeedOrder::FlumeSeq,
+                None,
+                first.after.as_ref().map(|c| c.as_str()),
+                10,
+            )
+            .unwrap();
+        assert!(newer.messages.is_empty());
+        append(
+            &mut view,
+            4,
+            ("%e", alice, 6000.0, json!({"type": "post", "text": "e"})),
+        );
+        append(
+            &mut view,
+            5,
+            ("%f", alice, 7000.0, json!({"type": "post", "text": "f"})),
+        );
+        let newer = reader
+            .author_feed(
+                alice,
+                FeedOrder::FlumeSeq,
+                None,
+                first.after.as_ref().map(|c| c.as_str()),
+                1,
+            )
+            .unwrap();
+        assert_eq!(page_keys(&newer), vec!["%e"]);
+        assert_eq!(newer.after, newer.before);
+
+        // A private message that couldn't be decrypted isn't public.
+        append(&mut view, 6, ("%g", bob, 8000.0, json!("c2VjcmV0.box")));
+        let public = reader
+            .public_feed(FeedOrder::FlumeSeq, None, None, 10)
+            .unwrap();
+        assert_eq!(page_keys(&public), vec!["%f", "%e", "%d", "%c", "%b", "%a"]);
+
+        let votes = reader
+            .type_feed("vote", FeedOrder::FlumeSeq, None, None, 10)
+            .unwrap();
+        assert_eq!(page_keys(&votes), vec!["%b"]);
+        let mentions = reader
+            .mentions_feed(alice, FeedOrder::FlumeSeq, None, None, 10)
+            .unwrap();
+        assert_eq!(page_keys(&mentions), vec!["%c"]);
+
+        assert!(reader
+            .public_feed(FeedOrder::FlumeSeq, Some("not a cursor"), None, 10)
+            .is_err());
+    }
+
+    #[test]
+    fn sort_time() {
+        let filename = "/tmp/test_sort_time.sqlite3";
+        let keys = Vec::new();
+        std::fs::remove_file(filename.clone())
+            .or::<Result<()>>(Ok(()))
+            .unwrap();
+
+        let mut view = FlumeViewSql::new(filename, keys, "").unwrap();
+        let alice = "@QlCTpvY7p9ty2yOFrv1WU1AE88aoQc4Y7wYal7PFc+w=.ed25519";
+
+        // (key, asserted, received). %b claims a time far in the future, and ties with %e once
+        // it's capped at when it was received. Ties are ordered by flume_seq.
+        let messages = [
+            ("%a", 1000.0, 5000.0),
+            ("%b", 99999.0, 6000.0),
+            ("%c", 3000.0, 7000.0),
+            ("%d", 8000.0, 8000.0),
+            ("%e", 6000.0, 9000.0),
+        ];
+
+        for (seq, (key, asserted, received)) in messages.iter().enumerate() {
+            let item = json!({
+                "key": key,
+                "value": {
+                    "author": alice,
+                    "sequence": seq + 1,
+                    "timestamp": asserted,
+                    "content": {"type": "post", "text": key}
+                },
+                "timestamp": received
+            });
+            view.append(seq as u64 * 100, &to_vec(&item).unwrap());
+        }
+
+        let reader = view.reader().unwrap();
+        let sort_time: f64 = reader
+            .connection()
+            .unwrap()
+            .query_row(
+                "SELECT sort_time FROM messages WHERE key = '%b'",
+                NO_PARAMS,
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(sort_time, 6000.0);
+
+        let page_keys = |page: &FeedPage| -> Vec<String> {
+            page.messages
+                .iter()
+                .map(|message| message.key.clone())
+                .collect()
+        };
+
+        let first = reader
+            .author_feed(alice, FeedOrder::SortTime, None, None, 2)
+            .unwrap();
+        assert_eq!(page_keys(&first), vec!["%d", "%e"]);
+        let second = reader
+            .author_feed(
+                alice,
+                FeedOrder::SortTime,
+                first.before.as_ref().map(|c| c.as_str()),
+                None,
+                2,
+            )
+            .unwrap();
+        assert_eq!(page_keys(&second), vec!["%b", "%c"]);
+        let newer = reader
+            .author_feed(
+                alice,
+                FeedOrder::SortTime,
+                None,
+                second.after.as_ref().map(|c| c.as_str()),
+                10,
+            )
+            .unwrap();
+        assert_eq!(page_keys(&newer), vec!["%d", "%e"]);
+
+        let by_asserted_time = reader
+            .author_feed(alice, FeedOrder::AssertedTime, None, None, 1)
+            .unwrap();
+        assert_eq!(page_keys(&by_asserted_time), vec!["%b"]);
+        drop(reader);
+        drop(view);
+
+        // A db from before sort_time gets the column on open instead of being rebuilt.
+        Connection::open(filename)
+            .unwrap()
+            .execute_batch(
+                "
+                PRAGMA legacy_alter_table = ON;
+                DROP VIEW messages;
+                ALTER TABLE messages_raw RENAME TO messages_old;
+                CREATE TABLE messages_raw AS SELECT flume_seq, key_id, seq, received_time,
+                  asserted_time, root_id, fork_id, author_id, content_type, content, is_decrypted,
+                  raw, previous_id, hash, signature, is_valid
+                FROM messages_old;
+                DROP TABLE messages_old;
+                ",
+            )
+            .unwrap();
+
+        let view = FlumeViewSql::new(filename, Vec::new(), "").unwrap();
+        assert!(view.last_rebuild().is_none());
+        let reader = view.reader().unwrap();
+        let first = reader
+            .author_feed(alice, FeedOrder::SortTime, None, None, 2)
+            .unwrap();
+        assert_eq!(page_keys(&first), vec!["%d", "%e"]);
+    }
+
+    #[test]
+    fn malformed_messages_are_errors() {
+        let filename = "/tmp/test_malformed_messages.sqlite3";
+        std::fs::remove_file(filename).unwrap_or(());
+
+        let mut view = FlumeViewSql::new(filename, Vec::new(), "").unwrap();
+        let message = |content: Value| {
+            to_vec(&json!({
+                "key": "%a",
+                "value": {
+                    "author": "@QlCTpvY7p9ty2yOFrv1WU1AE88aoQc4Y7wYal7PFc+w=.ed25519",
+                    "sequence": 1,
+                    "timestamp": 1000.0,
+                    "content": content
+                },
+                "timestamp": 1000.0
+            }))
+            .unwrap()
+        };
+
+        let items = vec![
+            b"BANG".to_vec(),
+            message(json!({"text": "no type"})),
+            message(json!("not base64!.box")),
+        ];
+        for item in items {
+            let err = view.append_batch(&[(0, item)]).unwrap_err();
+            match err.downcast_ref::<FlumeViewSqlError>() {
+                Some(FlumeViewSqlError::MalformedMessage { .. }) => (),
+                _ => panic!("unexpected error: {}", err),
+            }
+        }
+
+        assert_eq!(view.get_latest().unwrap(), 0);
+    }
+
+    struct PollsPlugin {
+        version: u32,
+    }
+
+    impl SqlIndexPlugin for PollsPlugin {
+        fn name(&self) -> &str {
+            "polls"
+        }
+
+        fn version(&self) -> u32 {
+            self.version
+        }
+
+        fn create_schema(
+            &self,
+            connection: &Connection,
+        ) -> std::result::Result<(), ::failure::Error> {
+            connection.execute(
+                "CREATE TABLE IF NOT EXISTS polls (key_id INTEGER, title TEXT)",
+                NO_PARAMS,
+            )?;
+            Ok(())
+        }
+
+        fn drop_schema(
+            &self,
+            connection: &Connection,
+        ) -> std::result::Result<(), ::failure::Error> {
+            connection.execute("DROP TABLE IF EXISTS polls", NO_PARAMS)?;
+            Ok(())
+        }
+
+        fn on_message(
+            &self,
+            connection: &Connection,
+            message: &SsbMessage,
+            message_key_id: i64,
+        ) -> std::result::Result<(), ::failure::Error> {
+            if message.value.content["type"] == "poll" {
+                connection.execute(
+                    "INSERT INTO polls (key_id, title) VALUES (?, ?)",
+                    &[
+                        &message_key_id as &ToSql,
+                        &message.value.content["title"].as_str(),
+                    ],
+                )?;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn custom_plugin() {
+        let filename = "/tmp/test_custom_plugin.sqlite3";
+        let keys = Vec::new();
+        std::fs::remove_file(filename.clone())
+            .or::<Result<()>>(Ok(()))
+            .unwrap();
+
+        let options = FlumeViewSqlOptions {
+            plugins: vec![Box::new(PollsPlugin { version: 1 })],
+            ..FlumeViewSqlOptions::default()
+        };
+        let mut view = FlumeViewSql::new_with_options(filename, keys, "", options).unwrap();
+
+        let item = json!({
+            "key": "%poll",
+            "value": {
+                "author": "@QlCTpvY7p9ty2yOFrv1WU1AE88aoQc4Y7wYal7PFc+w=.ed25519",
+                "sequence": 1,
+                "timestamp": 1543958997985.0,
+                "content": {"type": "poll", "title": "Lunch?"}
+            },
+            "timestamp": 1543959001933.0
+        });
+        view.append(0, &to_vec(&item).unwrap());
+
+        let connection = view.reader().unwrap().connection().unwrap();
+        let title: String = connection
+            .query_row("SELECT title FROM polls", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!(title, "Lunch?");
+
+        let options = FlumeViewSqlOptions {
+            plugins: vec![
+                Box::new(PollsPlugin { version: 1 }),
+                Box::new(PollsPlugin { version: 1 }),
+            ],
+            ..FlumeViewSqlOptions::default()
+        };
+        assert!(FlumeViewSql::new_with_options(filename, Vec::new(), "", options).is_err());
+    }
+
+    #[test]
+    fn rebuild_changed_plugin() {
+        let filename = "/tmp/test_rebuild_plugin.sqlite3";
+        std::fs::remove_file(filename.clone())
+            .or::<Result<()>>(Ok(()))
+            .unwrap();
+
+        let options = FlumeViewSqlOptions {
+            plugins: vec![Box::new(PollsPlugin { version: 1 })],
+            ..FlumeViewSqlOptions::default()
+        };
+        let mut view = FlumeViewSql::new_with_options(filename, Vec::new(), "", options).unwrap();
+
+        for seq in 1..4 {
+            let item = json!({
+                "key": format!("%poll{}", seq),
+                "value": {
+                    "author": "@QlCTpvY7p9ty2yOFrv1WU1AE88aoQc4Y7wYal7PFc+w=.ed25519",
+                    "sequence": seq,
+                    "timestamp": 1543958997985.0,
+                    "content": {"type": "poll", "title": "Lunch?", "text": "lunch"}
+                },
+                "timestamp": 1543959001933.0
+            });
+            view.append(seq * 100, &to_vec(&item).unwrap());
+        }
+        drop(view);
+
+        let options = FlumeViewSqlOptions {
+            plugins: vec![Box::new(PollsPlugin { version: 2 })],
+            ..FlumeViewSqlOptions::default()
+        };
+        let mut view = FlumeViewSql::new_with_options(filename, Vec::new(), "", options).unwrap();
+        assert_eq!(view.lagging_plugins(), &["polls".to_string()]);
+
+        let reader = view.reader().unwrap();
+        let count_polls = || -> i64 {
+            reader
+                .connection()
+                .unwrap()
+                .query_row("SELECT COUNT(*) FROM polls", NO_PARAMS, |row| row.get(0))
+                .unwrap()
+        };
+
+        // Only the changed plugin was rebuilt.
+        assert_eq!(count_polls(), 0);
+        assert_eq!(reader.search("lunch", 10).unwrap().len(), 3);
+
+        assert_eq!(view.catch_up_plugins(0).unwrap(), 0);
+        assert_eq!(count_polls(), 0);
+        assert_eq!(view.lagging_plugins(), &["polls".to_string()]);
+        assert_eq!(view.catch_up_plugins(2).unwrap(), 2);
+        assert_eq!(count_polls(), 2);
+        assert_eq!(view.catch_up_plugins(2).unwrap(), 1);
+        assert_eq!(count_polls(), 3);
+        assert!(view.lagging_plugins().is_empty());
+        assert_eq!(view.catch_up_plugins(2).unwrap(), 0);
+    }
 }
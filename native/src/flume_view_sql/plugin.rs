@@ -0,0 +1,41 @@
+use failure::Error;
+use rusqlite::Connection;
+
+use flume_view_sql::SsbMessage;
+
+// A group of derived tables that's kept up to date as messages are appended to the view. The
+// built in tables are plugins too, and applications can register their own with
+// `FlumeViewSqlOptions::plugins` to index custom message types.
+pub trait SqlIndexPlugin: Send {
+    // Identifies the plugin in the migrations table, so it has to be unique.
+    fn name(&self) -> &str;
+
+    // Bump this when the schema or the way messages are indexed changes. Only this plugin's tables
+    // are rebuilt, from the messages already in `messages_raw`.
+    fn version(&self) -> u32;
+
+    // Creates the plugin's tables, indices and views. Called when the db is created, and after
+    // `drop_schema` when the plugin's version changes.
+    fn create_schema(&self, connection: &Connection) -> Result<(), Error>;
+
+    // Drops everything `create_schema` created, of this or any earlier version of the plugin.
+    fn drop_schema(&self, connection: &Connection) -> Result<(), Error>;
+
+    // Called for every valid message, in the same transaction as the message is inserted into
+    // `messages_raw`, or later by `catch_up_plugins` if the plugin is being rebuilt.
+    // `message_key_id` is the id of the message's key in the `keys` table.
+    fn on_message(
+        &self,
+        connection: &Connection,
+        message: &SsbMessage,
+        message_key_id: i64,
+    ) -> Result<(), Error>;
+
+    // Deletes everything indexed from the messages of the author with id `author_id`. Called when
+    // the feed is blocked or unblocked with `FlumeViewSqlOptions::exclude_blocked_feeds` set, and
+    // after unblocking `on_message` is called again for each of the feed's messages. Return false
+    // if the plugin can't remove a single feed, and it will be rebuilt instead.
+    fn remove_feed(&self, _connection: &Connection, _author_id: i64) -> Result<bool, Error> {
+        Ok(false)
+    }
+}
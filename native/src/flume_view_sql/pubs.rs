@@ -0,0 +1,102 @@
+use flume_view_sql::*;
+use rusqlite::types::ToSql;
+use rusqlite::{Connection, Error, NO_PARAMS};
+
+// Records a `pub` announcement. Each author's latest announcement of each pub is kept.
+pub fn insert_pubs(connection: &Connection, message: &SsbMessage) -> Result<(), Error> {
+    if message.value.content["type"].as_str() != Some("pub") {
+        return Ok(());
+    }
+
+    let (host, port, key) = match parse_pub_address(&message.value.content["address"]) {
+        Some(address) => address,
+        None => return Ok(()),
+    };
+
+    let author_id = find_or_create_author(connection, &message.value.author)?;
+
+    connection
+        .prepare_cached("INSERT OR IGNORE INTO pubs (host, port, key, author_id, last_announced) VALUES (?, ?, ?, ?, ?)")?
+        .execute(&[
+            &host as &ToSql,
+            &port,
+            &key,
+            &author_id,
+            &message.value.timestamp,
+        ])?;
+
+    connection
+        .prepare_cached(
+            "
+            UPDATE pubs SET last_announced = MAX(last_announced, ?)
+            WHERE host = ? AND port = ? AND key = ? AND author_id = ?
+            ",
+        )?
+        .execute(&[
+            &message.value.timestamp as &ToSql,
+            &host,
+            &port,
+            &key,
+            &author_id,
+        ])?;
+
+    Ok(())
+}
+
+// Addresses are either `{ host, port, key }` or a multiserver address like
+// `net:host:8008~shs:<base64 key>`. Only the first net address of a multiserver address is used.
+fn parse_pub_address(address: &Value) -> Option<(String, i64, String)> {
+    match address {
+        Value::Object(_) => {
+            let host = address["host"].as_str()?;
+            let port = address["port"].as_i64()?;
+            let key = address["key"].as_str()?;
+            Some((host.to_string(), port, key.to_string()))
+        }
+        Value::String(multiserver) => multiserver.split(';').find_map(|address| {
+            let mut protocols = address.split('~');
+            let net: Vec<&str> = protocols.next()?.split(':').collect();
+            let shs: Vec<&str> = protocols.next()?.split(':').collect();
+
+            match (net.as_slice(), shs.as_slice()) {
+                (["net", host, port], ["shs", key]) => Some((
+                    host.to_string(),
+                    port.parse().ok()?,
+                    format!("@{}.ed25519", key),
+                )),
+                _ => None,
+            }
+        }),
+        _ => None,
+    }
+}
+
+pub fn create_pubs_tables(connection: &Connection) -> Result<usize, Error> {
+    trace!("Creating pubs tables");
+
+    // `key` is the pub's feed id and `author_id` the feed that announced it.
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS pubs (
+          id INTEGER PRIMARY KEY,
+          host TEXT,
+          port INTEGER,
+          key TEXT,
+          author_id INTEGER,
+          last_announced REAL
+        )",
+        NO_PARAMS,
+    )
+}
+
+pub fn create_pubs_indices(connection: &Connection) -> Result<usize, Error> {
+    trace!("Creating pubs indices");
+
+    connection.execute(
+        "CREATE UNIQUE INDEX IF NOT EXISTS pubs_address_author_id_index on pubs (host, port, key, author_id)",
+        NO_PARAMS,
+    )?;
+    connection.execute(
+        "CREATE INDEX IF NOT EXISTS pubs_key_index on pubs (key)",
+        NO_PARAMS,
+    )
+}
@@ -1,5 +1,8 @@
 use flume_view_sql::*;
-use rusqlite::{Connection, Error, NO_PARAMS};
+use rusqlite::{Connection, Error, Row, NO_PARAMS};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct BackLink {
@@ -8,6 +11,250 @@ pub struct BackLink {
     timestamp: f64,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Message {
+    pub key: String,
+    pub author: String,
+    pub seq: i64,
+    pub received_time: f64,
+    pub asserted_time: f64,
+    pub content: Value,
+    pub is_decrypted: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Profile {
+    pub id: String,
+    pub name: Option<String>,
+    pub image: Option<String>,
+    pub description: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct FeedIntegrity {
+    pub author: String,
+    pub message_count: i64,
+    pub first_seq: Option<i64>,
+    pub latest_seq: Option<i64>,
+    pub seq_gaps: Vec<SeqGap>,
+    pub chain_breaks: Vec<ChainBreak>,
+}
+
+// A run of sequence numbers, from `from` to `to` inclusive, that the view has no messages for.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct SeqGap {
+    pub from: i64,
+    pub to: i64,
+}
+
+// A message whose `previous` isn't the key of the message before it in the feed.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ChainBreak {
+    pub seq: i64,
+    pub key: String,
+    pub previous: Option<String>,
+    pub expected_previous: Vec<String>,
+}
+
+// Two or more messages that claim the same sequence number in a feed.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FeedFork {
+    pub author: String,
+    pub seq: i64,
+    pub keys: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FeedTip {
+    pub author: String,
+    pub latest_seq: i64,
+    pub latest_key: String,
+    pub message_count: i64,
+    pub first_seen: f64,
+    pub last_seen: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GitRepo {
+    pub key: String,
+    pub author: String,
+    pub name: Option<String>,
+    pub upstream: Option<String>,
+    pub asserted_time: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GitRef {
+    pub name: String,
+    pub sha: String,
+    // The key of the `git-update` message that last set the ref.
+    pub update_key: String,
+    pub asserted_time: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GitIssue {
+    pub key: String,
+    // Either "issue" or "pull-request".
+    pub kind: String,
+    pub author: String,
+    pub title: Option<String>,
+    pub asserted_time: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Gathering {
+    pub key: String,
+    pub author: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub location: Option<String>,
+    pub start_time: Option<f64>,
+    pub image: Option<String>,
+    pub attendee_count: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RankedPub {
+    pub host: String,
+    pub port: i64,
+    pub key: String,
+    pub last_announced: f64,
+    // How many of the feeds the ranking was for follows that announced or follow the pub.
+    pub friend_count: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Tag {
+    pub key: String,
+    pub author: String,
+    pub name: String,
+    // How many messages the tag's author currently has it on.
+    pub message_count: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Blob {
+    pub blob: String,
+    pub mime_type: Option<String>,
+    pub name: Option<String>,
+    pub size: Option<i64>,
+    pub width: Option<i64>,
+    pub height: Option<i64>,
+}
+
+// How a feed page is ordered. All are newest first. `FlumeSeq` is the order messages were
+// received in, `AssertedTime` is the time their authors claim to have written them. `SortTime` is
+// the claimed time, but never later than when the message was received, so a forged timestamp
+// can't keep a message at the top of a feed. It's the order Patchwork and ssb-sort use.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FeedOrder {
+    FlumeSeq,
+    AssertedTime,
+    SortTime,
+}
+
+impl FromStr for FeedOrder {
+    type Err = failure::Error;
+
+    fn from_str(order: &str) -> Result<FeedOrder, failure::Error> {
+        match order {
+            "flume_seq" => Ok(FeedOrder::FlumeSeq),
+            "asserted_time" => Ok(FeedOrder::AssertedTime),
+            "sort_time" => Ok(FeedOrder::SortTime),
+            _ => Err(format_err!("Unknown feed order: {}", order)),
+        }
+    }
+}
+
+// The position of a message in a feed, so the next page can start from it. Clients get it as an
+// opaque string and shouldn't rely on what's in it. It works with either `FeedOrder`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeedCursor {
+    pub flume_seq: i64,
+    pub asserted_time: f64,
+    pub sort_time: f64,
+}
+
+impl fmt::Display for FeedCursor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let cursor = format!(
+            "{}:{}:{}",
+            self.flume_seq, self.asserted_time, self.sort_time
+        );
+        write!(f, "{}", base64::encode(&cursor))
+    }
+}
+
+impl FromStr for FeedCursor {
+    type Err = failure::Error;
+
+    fn from_str(cursor: &str) -> Result<FeedCursor, failure::Error> {
+        let invalid = || format_err!("Invalid cursor: {}", cursor);
+
+        let decoded = base64::decode(cursor).map_err(|_| invalid())?;
+        let decoded = String::from_utf8(decoded).map_err(|_| invalid())?;
+        let mut parts = decoded.split(':');
+
+        let flume_seq = parts.next().and_then(|seq| seq.parse().ok());
+        let asserted_time = parts.next().and_then(|time| time.parse().ok());
+        let sort_time = parts.next().and_then(|time| time.parse().ok());
+        match (flume_seq, asserted_time, sort_time, parts.next()) {
+            (Some(flume_seq), Some(asserted_time), Some(sort_time), None) => Ok(FeedCursor {
+                flume_seq,
+                asserted_time,
+                sort_time,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+// A page of a feed, newest first. Pass `before` as the `before` cursor to get the next older page,
+// and `after` as the `after` cursor to get the messages that arrived since. They're None when the
+// page is empty, in which case keep using the cursor you had.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct FeedPage {
+    pub messages: Vec<Message>,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+// A query clients can subscribe to, to be told about new messages as they're indexed.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LiveQuery {
+    MessagesOfType { content_type: String },
+    // Mentions of `author`, or of me if `author` is missing.
+    Mentions { author: Option<String> },
+    ThreadReplies { root: String },
+}
+
+// Select these columns from the `messages` view to map rows with `message_from_row`. Queries should
+// leave out messages that failed validation with `messages.is_valid IS NOT 0`.
+const MESSAGE_COLUMNS: &str = "
+    messages.key,
+    messages.author,
+    messages.seq,
+    messages.received_time,
+    messages.asserted_time,
+    messages.content,
+    messages.is_decrypted
+";
+
+fn message_from_row(row: &Row) -> Message {
+    Message {
+        key: row.get(0),
+        author: row.get(1),
+        seq: row.get(2),
+        received_time: row.get(3),
+        asserted_time: row.get(4),
+        content: row.get::<usize, Option<Value>>(5).unwrap_or(Value::Null),
+        is_decrypted: row.get::<usize, Option<bool>>(6).unwrap_or(false),
+    }
+}
+
 pub fn back_link_references(
     connection: &Connection,
     id: &str,
@@ -38,8 +285,939 @@ pub fn back_link_references(
     rows.collect()
 }
 
+pub fn get_seq_by_key(connection: &Connection, key: &str) -> Result<i64, Error> {
+    let mut stmt = connection
+        .prepare_cached("SELECT flume_seq FROM messages_raw JOIN keys ON messages_raw.key_id=keys.id WHERE keys.key=?1")?;
+
+    stmt.query_row(&[key], |row| row.get(0))
+}
+
+pub fn get_seqs_by_type(connection: &Connection, content_type: &str) -> Result<Vec<i64>, Error> {
+    let mut stmt =
+        connection.prepare_cached("SELECT flume_seq FROM messages_raw WHERE content_type=?1")?;
+
+    let rows = stmt.query_map(&[content_type], |row| row.get(0))?;
+
+    rows.collect()
+}
+
+pub fn get_seqs_by_author(connection: &Connection, author: &str) -> Result<Vec<i64>, Error> {
+    let mut stmt = connection
+        .prepare_cached("SELECT flume_seq FROM messages_raw JOIN authors ON messages_raw.author_id=authors.id WHERE author=?1")?;
+
+    let rows = stmt.query_map(&[author], |row| row.get(0))?;
+
+    rows.collect()
+}
+
+// The root message and all the replies to it, oldest first.
+pub fn thread(connection: &Connection, root: &str) -> Result<Vec<Message>, Error> {
+    let mut stmt = connection.prepare_cached(&format!(
+        "
+        SELECT {}
+        FROM messages
+        WHERE (messages.key_id = (SELECT id FROM keys WHERE key = ?1)
+            OR messages.root_id = (SELECT id FROM keys WHERE key = ?1))
+        AND messages.is_valid IS NOT 0
+        ORDER BY messages.asserted_time ASC
+        ",
+        MESSAGE_COLUMNS
+    ))?;
+
+    let rows = stmt.query_map(&[root], message_from_row)?;
+
+    rows.collect()
+}
+
+// Messages by `author`, newest first. Pass the flume_seq of the last message of the previous page
+// as `before` to get the next page.
+pub fn feed(
+    connection: &Connection,
+    author: &str,
+    before: Option<i64>,
+    limit: i64,
+) -> Result<Vec<Message>, Error> {
+    let mut stmt = connection.prepare_cached(&format!(
+        "
+        SELECT {}
+        FROM messages
+        WHERE messages.author_id = (SELECT id FROM authors WHERE author = ?)
+        AND messages.flume_seq < ?
+        AND messages.is_valid IS NOT 0
+        ORDER BY messages.flume_seq DESC
+        LIMIT ?
+        ",
+        MESSAGE_COLUMNS
+    ))?;
+
+    let before = before.unwrap_or(std::i64::MAX);
+    let rows = stmt.query_map(&[&author as &ToSql, &before, &limit], message_from_row)?;
+
+    rows.collect()
+}
+
+// Messages matching `query` with a flume_seq in the range (after_seq, up_to_seq], oldest first.
+pub fn live_query_results(
+    connection: &Connection,
+    query: &LiveQuery,
+    after_seq: i64,
+    up_to_seq: i64,
+) -> Result<Vec<Message>, Error> {
+    let (condition, param): (&str, Option<&str>) = match query {
+        LiveQuery::MessagesOfType { content_type } => {
+            ("messages.content_type = ?", Some(content_type.as_str()))
+        }
+        LiveQuery::Mentions {
+            author: Some(author),
+        } => (
+            "messages.key_id IN (
+                SELECT link_from_key_id FROM mentions_raw
+                WHERE link_to_author_id = (SELECT id FROM authors WHERE author = ?)
+            )",
+            Some(author.as_str()),
+        ),
+        LiveQuery::Mentions { author: None } => (
+            "messages.key_id IN (
+                SELECT link_from_key_id FROM mentions_raw
+                WHERE link_to_author_id IN (SELECT id FROM authors WHERE is_me = 1)
+            )",
+            None,
+        ),
+        LiveQuery::ThreadReplies { root } => (
+            "messages.root_id = (SELECT id FROM keys WHERE key = ?)",
+            Some(root.as_str()),
+        ),
+    };
+
+    let mut stmt = connection.prepare_cached(&format!(
+        "
+        SELECT {}
+        FROM messages
+        WHERE messages.flume_seq > ?
+        AND messages.flume_seq <= ?
+        AND messages.is_valid IS NOT 0
+        AND {}
+        ORDER BY messages.flume_seq ASC
+        ",
+        MESSAGE_COLUMNS, condition
+    ))?;
+
+    let rows = match param {
+        Some(param) => stmt.query_map(
+            &[&after_seq as &ToSql, &up_to_seq, &param],
+            message_from_row,
+        )?,
+        None => stmt.query_map(&[&after_seq as &ToSql, &up_to_seq], message_from_row)?,
+    };
+
+    rows.collect()
+}
+
+// The number of rows in each table in the db, not counting sqlite's internal tables.
+pub fn table_row_counts(connection: &Connection) -> Result<Vec<(String, i64)>, Error> {
+    let mut stmt = connection.prepare(
+        "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name",
+    )?;
+
+    let table_names = stmt
+        .query_map(NO_PARAMS, |row| row.get::<usize, String>(0))?
+        .collect::<Result<Vec<String>, Error>>()?;
+
+    table_names
+        .into_iter()
+        .map(|table_name| {
+            connection
+                .query_row(
+                    &format!("SELECT COUNT(*) FROM \"{}\"", table_name),
+                    NO_PARAMS,
+                    |row| row.get(0),
+                )
+                .map(|count| (table_name, count))
+        })
+        .collect()
+}
+
+// Authors who follow `id` and are followed by `id`.
+pub fn friends(connection: &Connection, id: &str) -> Result<Vec<String>, Error> {
+    let mut stmt = connection.prepare_cached(
+        "
+        SELECT DISTINCT authors.author
+        FROM contacts_raw
+        JOIN contacts_raw AS reverse_contacts_raw
+            ON reverse_contacts_raw.author_id = contacts_raw.contact_author_id
+            AND reverse_contacts_raw.contact_author_id = contacts_raw.author_id
+        JOIN authors ON authors.id = contacts_raw.contact_author_id
+        WHERE contacts_raw.author_id = (SELECT id FROM authors WHERE author = ?1)
+        AND contacts_raw.state = 1
+        AND reverse_contacts_raw.state = 1
+        ",
+    )?;
+
+    let rows = stmt.query_map(&[id], |row| row.get(0))?;
+
+    rows.collect()
+}
+
+// Full text search over the `text` field of messages, best matches first. `query` uses the sqlite
+// fts5 query syntax.
+pub fn search(connection: &Connection, query: &str, limit: i64) -> Result<Vec<Message>, Error> {
+    let mut stmt = connection.prepare_cached(&format!(
+        "
+        SELECT {}
+        FROM search_fts
+        JOIN messages ON messages.key_id = search_fts.rowid
+        WHERE search_fts MATCH ?
+        AND messages.is_valid IS NOT 0
+        ORDER BY search_fts.rank
+        LIMIT ?
+        ",
+        MESSAGE_COLUMNS
+    ))?;
+
+    let rows = stmt.query_map(&[&query as &ToSql, &limit], message_from_row)?;
+
+    rows.collect()
+}
+
+// The latest name, image and description that `id` has set for themselves.
+pub fn profile(connection: &Connection, id: &str) -> Result<Profile, Error> {
+    let mut stmt = connection.prepare_cached(
+        "
+        SELECT messages_raw.content
+        FROM abouts_raw
+        JOIN messages_raw ON messages_raw.key_id = abouts_raw.link_from_key_id
+        WHERE abouts_raw.link_to_author_id = (SELECT id FROM authors WHERE author = ?1)
+        AND messages_raw.author_id = abouts_raw.link_to_author_id
+        ORDER BY messages_raw.flume_seq ASC
+        ",
+    )?;
+
+    let rows = stmt.query_map(&[id], |row| row.get::<usize, Option<Value>>(0))?;
+
+    let mut profile = Profile {
+        id: id.to_string(),
+        ..Profile::default()
+    };
+
+    for row in rows {
+        let content = match row? {
+            Some(content) => content,
+            None => continue,
+        };
+
+        if let Value::String(name) = &content["name"] {
+            profile.name = Some(name.clone());
+        }
+
+        match &content["image"] {
+            Value::String(image) => profile.image = Some(image.clone()),
+            Value::Object(image) => {
+                if let Some(Value::String(link)) = image.get("link") {
+                    profile.image = Some(link.clone())
+                }
+            }
+            _ => (),
+        }
+
+        if let Value::String(description) = &content["description"] {
+            profile.description = Some(description.clone());
+        }
+    }
+
+    Ok(profile)
+}
+
+// Checks the `previous` chain and sequence numbers of every message by `author` in the view.
+// Missing messages before the first one we have aren't counted as a gap.
+pub fn feed_integrity(connection: &Connection, author: &str) -> Result<FeedIntegrity, Error> {
+    let mut stmt = connection.prepare_cached(
+        "
+        SELECT messages_raw.seq, keys.key, previous_keys.key
+        FROM messages_raw
+        JOIN keys ON keys.id = messages_raw.key_id
+        LEFT JOIN keys AS previous_keys ON previous_keys.id = messages_raw.previous_id
+        WHERE messages_raw.author_id = (SELECT id FROM authors WHERE author = ?1)
+        ORDER BY messages_raw.seq ASC
+        ",
+    )?;
+
+    let rows = stmt
+        .query_map(&[author], |row| {
+            (
+                row.get::<usize, i64>(0),
+                row.get::<usize, String>(1),
+                row.get::<usize, Option<String>>(2),
+            )
+        })?
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let mut keys_by_seq: HashMap<i64, Vec<String>> = HashMap::new();
+    for (seq, key, _) in &rows {
+        keys_by_seq
+            .entry(*seq)
+            .or_insert_with(Vec::new)
+            .push(key.clone());
+    }
+
+    let mut integrity = FeedIntegrity {
+        author: author.to_string(),
+        message_count: rows.len() as i64,
+        first_seq: rows.first().map(|(seq, _, _)| *seq),
+        latest_seq: rows.last().map(|(seq, _, _)| *seq),
+        ..FeedIntegrity::default()
+    };
+
+    let mut last_seq: Option<i64> = None;
+
+    for (seq, key, previous) in rows {
+        if let Some(last_seq) = last_seq {
+            if seq > last_seq + 1 {
+                integrity.seq_gaps.push(SeqGap {
+                    from: last_seq + 1,
+                    to: seq - 1,
+                });
+            }
+        }
+        last_seq = Some(seq);
+
+        let expected_previous = keys_by_seq.get(&(seq - 1)).cloned().unwrap_or_default();
+
+        let is_break = if seq == 1 {
+            previous.is_some()
+        } else {
+            // We can only check the link if we have the message before this one.
+            !expected_previous.is_empty()
+                && !previous
+                    .as_ref()
+                    .map(|previous| expected_previous.contains(previous))
+                    .unwrap_or(false)
+        };
+
+        if is_break {
+            integrity.chain_breaks.push(ChainBreak {
+                seq,
+                key,
+                previous,
+                expected_previous,
+            });
+        }
+    }
+
+    Ok(integrity)
+}
+
+// Every fork recorded in `feed_forks`, with the keys of all the messages that claim each sequence.
+pub fn forked_feeds(connection: &Connection) -> Result<Vec<FeedFork>, Error> {
+    let mut stmt = connection.prepare_cached(
+        "
+        SELECT authors.author, feed_forks.seq, keys.key, conflicting_keys.key
+        FROM feed_forks
+        JOIN authors ON authors.id = feed_forks.author_id
+        JOIN keys ON keys.id = feed_forks.key_id
+        JOIN keys AS conflicting_keys ON conflicting_keys.id = feed_forks.conflicting_key_id
+        ORDER BY authors.author, feed_forks.seq, feed_forks.id
+        ",
+    )?;
+
+    let rows = stmt.query_map(NO_PARAMS, |row| {
+        (
+            row.get::<usize, String>(0),
+            row.get::<usize, i64>(1),
+            row.get::<usize, String>(2),
+            row.get::<usize, String>(3),
+        )
+    })?;
+
+    let mut forks: Vec<FeedFork> = Vec::new();
+
+    for row in rows {
+        let (author, seq, key, conflicting_key) = row?;
+
+        let is_same_fork = forks
+            .last()
+            .map(|fork| fork.author == author && fork.seq == seq)
+            .unwrap_or(false);
+
+        if !is_same_fork {
+            forks.push(FeedFork {
+                author,
+                seq,
+                keys: Vec::new(),
+            });
+        }
+
+        let fork = forks.last_mut().unwrap();
+        for key in [key, conflicting_key].iter() {
+            if !fork.keys.contains(key) {
+                fork.keys.push(key.clone());
+            }
+        }
+    }
+
+    Ok(forks)
+}
+
+// The latest message of every feed, most recently active first. Pass `active_since` to only get
+// feeds we've received a message from since then.
+pub fn feed_tips(
+    connection: &Connection,
+    active_since: Option<f64>,
+) -> Result<Vec<FeedTip>, Error> {
+    let mut stmt = connection.prepare_cached(
+        "
+        SELECT authors.author, feeds.latest_seq, keys.key, feeds.message_count, feeds.first_seen, feeds.last_seen
+        FROM feeds
+        JOIN authors ON authors.id = feeds.author_id
+        JOIN keys ON keys.id = feeds.latest_key_id
+        WHERE feeds.last_seen >= ?
+        ORDER BY feeds.last_seen DESC
+        ",
+    )?;
+
+    let active_since = active_since.unwrap_or(std::f64::MIN);
+    let rows = stmt.query_map(&[&active_since], |row| FeedTip {
+        author: row.get(0),
+        latest_seq: row.get(1),
+        latest_key: row.get(2),
+        message_count: row.get(3),
+        first_seen: row.get(4),
+        last_seen: row.get(5),
+    })?;
+
+    rows.collect()
+}
+
+// The git-ssb repos created by `author`, newest first.
+pub fn repos_by_author(connection: &Connection, author: &str) -> Result<Vec<GitRepo>, Error> {
+    let mut stmt = connection.prepare_cached(
+        "
+        SELECT keys.key, authors.author, git_repos.name, upstream_keys.key, git_repos.asserted_time
+        FROM git_repos
+        JOIN keys ON keys.id = git_repos.key_id
+        JOIN authors ON authors.id = git_repos.author_id
+        LEFT JOIN keys AS upstream_keys ON upstream_keys.id = git_repos.upstream_key_id
+        WHERE authors.author = ?
+        ORDER BY git_repos.asserted_time DESC
+        ",
+    )?;
+
+    let rows = stmt.query_map(&[author], |row| GitRepo {
+        key: row.get(0),
+        author: row.get(1),
+        name: row.get(2),
+        upstream: row.get(3),
+        asserted_time: row.get(4),
+    })?;
+
+    rows.collect()
+}
+
+// The latest sha of every ref of `repo`. Deleted refs are left out.
+pub fn repo_refs(connection: &Connection, repo: &str) -> Result<Vec<GitRef>, Error> {
+    let mut stmt = connection.prepare_cached(
+        "
+        SELECT git_refs.ref, git_refs.sha, update_keys.key, git_refs.asserted_time
+        FROM git_refs
+        JOIN keys AS repo_keys ON repo_keys.id = git_refs.repo_key_id
+        JOIN keys AS update_keys ON update_keys.id = git_refs.update_key_id
+        WHERE repo_keys.key = ?
+        ORDER BY git_refs.ref
+        ",
+    )?;
+
+    let rows = stmt.query_map(&[repo], |row| GitRef {
+        name: row.get(0),
+        sha: row.get(1),
+        update_key: row.get(2),
+        asserted_time: row.get(3),
+    })?;
+
+    rows.collect()
+}
+
+// The issues and pull requests of `repo` that haven't been closed, newest first.
+pub fn open_issues(connection: &Connection, repo: &str) -> Result<Vec<GitIssue>, Error> {
+    let mut stmt = connection.prepare_cached(
+        "
+        SELECT keys.key, git_issues.kind, authors.author, git_issues.title, git_issues.asserted_time
+        FROM git_issues
+        JOIN keys ON keys.id = git_issues.key_id
+        JOIN keys AS repo_keys ON repo_keys.id = git_issues.repo_key_id
+        JOIN authors ON authors.id = git_issues.author_id
+        WHERE repo_keys.key = ? AND git_issues.is_open = 1
+        ORDER BY git_issues.asserted_time DESC
+        ",
+    )?;
+
+    let rows = stmt.query_map(&[repo], |row| GitIssue {
+        key: row.get(0),
+        kind: row.get(1),
+        author: row.get(2),
+        title: row.get(3),
+        asserted_time: row.get(4),
+    })?;
+
+    rows.collect()
+}
+
+// Gatherings that start at or after `after`, soonest first. Gatherings without a start time are
+// left out.
+pub fn upcoming_gatherings(
+    connection: &Connection,
+    after: f64,
+    limit: i64,
+) -> Result<Vec<Gathering>, Error> {
+    let mut stmt = connection.prepare_cached(
+        "
+        SELECT
+          keys.key,
+          authors.author,
+          gatherings.title,
+          gatherings.description,
+          gatherings.location,
+          gatherings.start_time,
+          gatherings.image,
+          (
+            SELECT COUNT(*) FROM gathering_attendees
+            WHERE gathering_attendees.gathering_key_id = gatherings.key_id
+            AND gathering_attendees.is_attending = 1
+          )
+        FROM gatherings
+        JOIN keys ON keys.id = gatherings.key_id
+        JOIN authors ON authors.id = gatherings.author_id
+        WHERE gatherings.start_time >= ?
+        ORDER BY gatherings.start_time
+        LIMIT ?
+        ",
+    )?;
+
+    let rows = stmt.query_map(&[&after as &ToSql, &limit], |row| Gathering {
+        key: row.get(0),
+        author: row.get(1),
+        title: row.get(2),
+        description: row.get(3),
+        location: row.get(4),
+        start_time: row.get(5),
+        image: row.get(6),
+        attendee_count: row.get(7),
+    })?;
+
+    rows.collect()
+}
+
+// The feeds currently attending `gathering`, in the order they said they'd attend.
+pub fn gathering_attendees(connection: &Connection, gathering: &str) -> Result<Vec<String>, Error> {
+    let mut stmt = connection.prepare_cached(
+        "
+        SELECT authors.author
+        FROM gathering_attendees
+        JOIN keys ON keys.id = gathering_attendees.gathering_key_id
+        JOIN authors ON authors.id = gathering_attendees.author_id
+        WHERE keys.key = ? AND gathering_attendees.is_attending = 1
+        ORDER BY gathering_attendees.asserted_time
+        ",
+    )?;
+
+    let rows = stmt.query_map(&[gathering], |row| row.get(0))?;
+
+    rows.collect()
+}
+
+// Every announced pub, ranked by how many of the feeds `id` follows announced it or follow it.
+// Pubs announced at more than one address are listed at their most recently announced one.
+pub fn ranked_pubs(connection: &Connection, id: &str) -> Result<Vec<RankedPub>, Error> {
+    // With MAX(), sqlite takes the other columns from the row with the max value.
+    let mut stmt = connection.prepare_cached(
+        "
+        SELECT
+          pubs.host,
+          pubs.port,
+          pubs.key,
+          MAX(pubs.last_announced) AS last_announced,
+          (
+            SELECT COUNT(DISTINCT follows.contact_author_id)
+            FROM contacts_raw AS follows
+            WHERE follows.author_id = (SELECT id FROM authors WHERE author = ?1)
+            AND follows.state = 1
+            AND (
+              follows.contact_author_id IN (
+                SELECT announcements.author_id FROM pubs AS announcements
+                WHERE announcements.key = pubs.key
+              )
+              OR follows.contact_author_id IN (
+                SELECT pub_follows.author_id FROM contacts_raw AS pub_follows
+                WHERE pub_follows.contact_author_id = (SELECT id FROM authors WHERE author = pubs.key)
+                AND pub_follows.state = 1
+              )
+            )
+          ) AS friend_count
+        FROM pubs
+        GROUP BY pubs.key
+        ORDER BY friend_count DESC, last_announced DESC
+        ",
+    )?;
+
+    let rows = stmt.query_map(&[id], |row| RankedPub {
+        host: row.get(0),
+        port: row.get(1),
+        key: row.get(2),
+        last_announced: row.get(3),
+        friend_count: row.get(4),
+    })?;
+
+    rows.collect()
+}
+
+// Select these columns from `tags` to map rows with `tag_from_row`.
+const TAG_COLUMNS: &str = "
+    keys.key,
+    authors.author,
+    tags.name,
+    (
+      SELECT COUNT(*) FROM tagged_messages AS tag_counts
+      WHERE tag_counts.tag_key_id = tags.key_id
+      AND tag_counts.author_id = tags.author_id
+      AND tag_counts.is_tagged = 1
+    )
+";
+
+fn tag_from_row(row: &Row) -> Tag {
+    Tag {
+        key: row.get(0),
+        author: row.get(1),
+        name: row.get(2),
+        message_count: row.get(3),
+    }
+}
+
+// The tags I've created, by name.
+pub fn my_tags(connection: &Connection) -> Result<Vec<Tag>, Error> {
+    let mut stmt = connection.prepare_cached(&format!(
+        "
+        SELECT {}
+        FROM tags
+        JOIN keys ON keys.id = tags.key_id
+        JOIN authors ON authors.id = tags.author_id
+        WHERE authors.is_me = 1
+        ORDER BY tags.name
+        ",
+        TAG_COLUMNS
+    ))?;
+
+    let rows = stmt.query_map(NO_PARAMS, tag_from_row)?;
+
+    rows.collect()
+}
+
+// The messages that currently have `tag`, most recently tagged first.
+pub fn messages_with_tag(connection: &Connection, tag: &str) -> Result<Vec<Message>, Error> {
+    let mut stmt = connection.prepare_cached(&format!(
+        "
+        SELECT {}
+        FROM tagged_messages
+        JOIN messages ON messages.key_id = tagged_messages.message_key_id
+        WHERE tagged_messages.tag_key_id = (SELECT id FROM keys WHERE key = ?)
+        AND tagged_messages.is_tagged = 1
+        AND messages.is_valid IS NOT 0
+        GROUP BY messages.key_id
+        ORDER BY MAX(tagged_messages.asserted_time) DESC
+        ",
+        MESSAGE_COLUMNS
+    ))?;
+
+    let rows = stmt.query_map(&[tag], message_from_row)?;
+
+    rows.collect()
+}
+
+// The tags currently on `message`, by name.
+pub fn tags_on_message(connection: &Connection, message: &str) -> Result<Vec<Tag>, Error> {
+    let mut stmt = connection.prepare_cached(&format!(
+        "
+        SELECT DISTINCT {}
+        FROM tagged_messages
+        JOIN tags ON tags.key_id = tagged_messages.tag_key_id
+        JOIN keys ON keys.id = tags.key_id
+        JOIN authors ON authors.id = tags.author_id
+        WHERE tagged_messages.message_key_id = (SELECT id FROM keys WHERE key = ?)
+        AND tagged_messages.is_tagged = 1
+        ORDER BY tags.name
+        ",
+        TAG_COLUMNS
+    ))?;
+
+    let rows = stmt.query_map(&[message], tag_from_row)?;
+
+    rows.collect()
+}
+
+// Select these columns from `blobs` to map rows with `blob_from_row`.
+const BLOB_COLUMNS: &str = "
+    blobs.blob,
+    blobs.mime_type,
+    blobs.name,
+    blobs.size,
+    blobs.width,
+    blobs.height
+";
+
+fn blob_from_row(row: &Row) -> Blob {
+    Blob {
+        blob: row.get(0),
+        mime_type: row.get(1),
+        name: row.get(2),
+        size: row.get(3),
+        width: row.get(4),
+        height: row.get(5),
+    }
+}
+
+// `id` and the feeds within `hops` follows of it. Use it in a `WITH RECURSIVE` clause, with `id`
+// as ?1 and `hops` as ?2.
+const HOPS_CTE: &str = "
+    hops(author_id, depth) AS (
+      SELECT id, 0 FROM authors WHERE author = ?1
+      UNION
+      SELECT contacts_raw.contact_author_id, hops.depth + 1
+      FROM contacts_raw
+      JOIN hops ON contacts_raw.author_id = hops.author_id
+      WHERE contacts_raw.state = 1 AND hops.depth < ?2
+    )
+";
+
+// The blobs linked to by `id` or the feeds within `hops` follows of `id`. `hops` of 0 is just
+// `id`'s own blobs.
+pub fn blobs_within_hops(connection: &Connection, id: &str, hops: i64) -> Result<Vec<Blob>, Error> {
+    let mut stmt = connection.prepare_cached(&format!(
+        "
+        WITH RECURSIVE {}
+        SELECT DISTINCT {}
+        FROM blob_links_raw
+        JOIN blobs ON blobs.id = blob_links_raw.link_to_blob_id
+        JOIN messages ON messages.key_id = blob_links_raw.link_from_key_id
+        WHERE messages.author_id IN (SELECT author_id FROM hops)
+        AND messages.is_valid IS NOT 0
+        ORDER BY blobs.blob
+        ",
+        HOPS_CTE, BLOB_COLUMNS
+    ))?;
+
+    let rows = stmt.query_map(&[&id as &ToSql, &hops], blob_from_row)?;
+
+    rows.collect()
+}
+
+// The blobs linked to from my messages.
+pub fn my_blobs(connection: &Connection) -> Result<Vec<Blob>, Error> {
+    let mut stmt = connection.prepare_cached(&format!(
+        "
+        SELECT DISTINCT {}
+        FROM blob_links_raw
+        JOIN blobs ON blobs.id = blob_links_raw.link_to_blob_id
+        JOIN messages ON messages.key_id = blob_links_raw.link_from_key_id
+        JOIN authors ON authors.id = messages.author_id
+        WHERE authors.is_me = 1
+        ORDER BY blobs.blob
+        ",
+        BLOB_COLUMNS
+    ))?;
+
+    let rows = stmt.query_map(NO_PARAMS, blob_from_row)?;
+
+    rows.collect()
+}
+
+// Public messages from every feed. Private messages that couldn't be decrypted are left out too,
+// as their content is a string without a type. See `FeedPage` for how to page through them.
+pub fn public_feed(
+    connection: &Connection,
+    order: FeedOrder,
+    before: Option<&FeedCursor>,
+    after: Option<&FeedCursor>,
+    limit: i64,
+) -> Result<FeedPage, Error> {
+    feed_page(
+        connection,
+        "messages.is_decrypted IS NOT 1 AND messages.content_type IS NOT NULL",
+        None,
+        order,
+        before,
+        after,
+        limit,
+    )
+}
+
+// Messages by `author`, public and private.
+pub fn author_feed(
+    connection: &Connection,
+    author: &str,
+    order: FeedOrder,
+    before: Option<&FeedCursor>,
+    after: Option<&FeedCursor>,
+    limit: i64,
+) -> Result<FeedPage, Error> {
+    feed_page(
+        connection,
+        "messages.author_id = (SELECT id FROM authors WHERE author = ?)",
+        Some(author),
+        order,
+        before,
+        after,
+        limit,
+    )
+}
+
+// Messages with a content type of `content_type`.
+pub fn type_feed(
+    connection: &Connection,
+    content_type: &str,
+    order: FeedOrder,
+    before: Option<&FeedCursor>,
+    after: Option<&FeedCursor>,
+    limit: i64,
+) -> Result<FeedPage, Error> {
+    feed_page(
+        connection,
+        "messages.content_type = ?",
+        Some(content_type),
+        order,
+        before,
+        after,
+        limit,
+    )
+}
+
+// Messages that mention `author`.
+pub fn mentions_feed(
+    connection: &Connection,
+    author: &str,
+    order: FeedOrder,
+    before: Option<&FeedCursor>,
+    after: Option<&FeedCursor>,
+    limit: i64,
+) -> Result<FeedPage, Error> {
+    feed_page(
+        connection,
+        "messages.key_id IN (
+            SELECT link_from_key_id FROM mentions_raw
+            WHERE link_to_author_id = (SELECT id FROM authors WHERE author = ?)
+        )",
+        Some(author),
+        order,
+        before,
+        after,
+        limit,
+    )
+}
+
+// Cursors compare against the ordering columns rather than skipping rows, so each page only reads
+// as many rows as it returns, however far back it is.
+fn feed_page(
+    connection: &Connection,
+    condition: &str,
+    param: Option<&str>,
+    order: FeedOrder,
+    before: Option<&FeedCursor>,
+    after: Option<&FeedCursor>,
+    limit: i64,
+) -> Result<FeedPage, Error> {
+    let mut conditions = vec![condition.to_string()];
+    let mut params: Vec<&ToSql> = Vec::new();
+    if let Some(param) = &param {
+        params.push(param);
+    }
+
+    for &(cursor, comparison) in &[(before, "<"), (after, ">")] {
+        if let Some(cursor) = cursor {
+            let (cursor_condition, cursor_params) = cursor_condition(order, cursor, comparison);
+            conditions.push(cursor_condition);
+            params.extend(cursor_params);
+        }
+    }
+
+    // With only an `after` cursor the page starts just after it, so the oldest messages come first
+    // and the page is reversed.
+    let ascending = after.is_some() && before.is_none();
+    let direction = if ascending { "ASC" } else { "DESC" };
+    let order_by = match order {
+        FeedOrder::FlumeSeq => format!("messages.flume_seq {}", direction),
+        FeedOrder::AssertedTime => format!(
+            "messages.asserted_time {0}, messages.flume_seq {0}",
+            direction
+        ),
+        FeedOrder::SortTime => format!("messages.sort_time {0}, messages.flume_seq {0}", direction),
+    };
+
+    let mut stmt = connection.prepare_cached(&format!(
+        "
+        SELECT {}, messages.flume_seq, messages.sort_time
+        FROM messages
+        WHERE messages.is_valid IS NOT 0
+        AND {}
+        ORDER BY {}
+        LIMIT ?
+        ",
+        MESSAGE_COLUMNS,
+        conditions.join(" AND "),
+        order_by
+    ))?;
+
+    params.push(&limit);
+    let rows = stmt.query_map(&params, |row| {
+        let message = message_from_row(row);
+        let cursor = FeedCursor {
+            flume_seq: row.get(7),
+            asserted_time: message.asserted_time,
+            sort_time: row.get(8),
+        };
+        (message, cursor)
+    })?;
+
+    let mut rows = rows.collect::<Result<Vec<_>, Error>>()?;
+    if ascending {
+        rows.reverse();
+    }
+
+    let after = rows.first().map(|(_, cursor)| cursor.to_string());
+    let before = rows.last().map(|(_, cursor)| cursor.to_string());
+
+    Ok(FeedPage {
+        messages: rows.into_iter().map(|(message, _)| message).collect(),
+        before,
+        after,
+    })
+}
+
+// The condition for messages that come before (`<`) or after (`>`) `cursor`. Messages with the
+// same time are ordered by flume_seq, which the row value comparison can use an index for.
+fn cursor_condition<'a>(
+    order: FeedOrder,
+    cursor: &'a FeedCursor,
+    comparison: &str,
+) -> (String, Vec<&'a ToSql>) {
+    match order {
+        FeedOrder::FlumeSeq => (
+            format!("messages.flume_seq {} ?", comparison),
+            vec![&cursor.flume_seq as &ToSql],
+        ),
+        FeedOrder::AssertedTime => (
+            format!(
+                "(messages.asserted_time, messages.flume_seq) {} (?, ?)",
+                comparison
+            ),
+            vec![&cursor.asserted_time as &ToSql, &cursor.flume_seq],
+        ),
+        FeedOrder::SortTime => (
+            format!(
+                "(messages.sort_time, messages.flume_seq) {} (?, ?)",
+                comparison
+            ),
+            vec![&cursor.sort_time as &ToSql, &cursor.flume_seq],
+        ),
+    }
+}
+
 pub fn how_many_friends_follow_id() {}
-pub fn who_is_friends_with_id() {}
 pub fn who_does_id_follow_one_way() {}
 pub fn who_does_follows_id_one_way() {}
 
@@ -71,7 +1249,6 @@ pub fn friends_two_hops(connection: Connection) {
 }
 #[cfg(test)]
 mod test {
-    use flume_view_sql::queries::back_link_references;
     use flume_view_sql::*;
     use flumedb::offset_log::OffsetLogIter;
     use itertools::Itertools;
@@ -84,12 +1261,9 @@ mod test {
             "/home/piet/.ssb/flume/log.offset",
             "/tmp/backlinks.sqlite3",
         );
-        let connection = &view.connection;
-        let links = back_link_references(
-            connection,
-            "%ZEuQdC7OBxDgRg2Vv/VgjArRIpE5YwIMo6ufXqaWaGg=.sha256",
-            0.0,
-        );
+        let reader = view.reader().unwrap();
+        let links = reader
+            .back_link_references("%ZEuQdC7OBxDgRg2Vv/VgjArRIpE5YwIMo6ufXqaWaGg=.sha256", 0.0);
         assert_eq!(links.unwrap().len(), 1);
     }
     fn create_test_db(
@@ -109,7 +1283,7 @@ mod test {
             .chunks(1000 as usize)
             .into_iter()
             .for_each(|chunk| {
-                view.append_batch(&chunk.collect_vec());
+                view.append_batch(&chunk.collect_vec()).unwrap();
             });
 
         view
@@ -0,0 +1,292 @@
+use failure::Error;
+use flumedb::flume_view::Sequence;
+use r2d2::{Pool, PooledConnection};
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::OpenFlags;
+use std::io::Write;
+
+use flume_view_sql::blob_store::*;
+use flume_view_sql::export::*;
+use flume_view_sql::get_latest;
+use flume_view_sql::hidden::*;
+use flume_view_sql::queries::*;
+
+// A read only handle to the view. It's cheap to clone and can be shared between threads, so
+// queries can run while another thread is appending to the view.
+#[derive(Clone)]
+pub struct SqlReader {
+    pool: Pool<SqliteConnectionManager>,
+}
+
+impl SqlReader {
+    pub fn new(path: &str) -> Result<SqlReader, Error> {
+        let flags: OpenFlags = OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+
+        let manager = SqliteConnectionManager::file(path).with_flags(flags);
+        let pool = Pool::new(manager)?;
+
+        Ok(SqlReader { pool })
+    }
+
+    pub fn connection(&self) -> Result<PooledConnection<SqliteConnectionManager>, Error> {
+        self.pool.get().map_err(|err| err.into())
+    }
+
+    pub fn get_latest(&self) -> Result<Sequence, Error> {
+        let connection = self.connection()?;
+        get_latest(&connection)
+    }
+
+    pub fn get_seq_by_key(&self, key: &str) -> Result<i64, Error> {
+        let connection = self.connection()?;
+        get_seq_by_key(&connection, key).map_err(|err| err.into())
+    }
+
+    pub fn get_seqs_by_type(&self, content_type: &str) -> Result<Vec<i64>, Error> {
+        let connection = self.connection()?;
+        get_seqs_by_type(&connection, content_type).map_err(|err| err.into())
+    }
+
+    pub fn get_seqs_by_author(&self, author: &str) -> Result<Vec<i64>, Error> {
+        let connection = self.connection()?;
+        get_seqs_by_author(&connection, author).map_err(|err| err.into())
+    }
+
+    pub fn back_link_references(&self, id: &str, timestamp: f64) -> Result<Vec<BackLink>, Error> {
+        let connection = self.connection()?;
+        back_link_references(&connection, id, timestamp).map_err(|err| err.into())
+    }
+
+    pub fn thread(&self, root: &str) -> Result<Vec<Message>, Error> {
+        let connection = self.connection()?;
+        thread(&connection, root).map_err(|err| err.into())
+    }
+
+    pub fn feed(
+        &self,
+        author: &str,
+        before: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<Message>, Error> {
+        let connection = self.connection()?;
+        feed(&connection, author, before, limit).map_err(|err| err.into())
+    }
+
+    pub fn live_query_results(
+        &self,
+        query: &LiveQuery,
+        after_seq: i64,
+        up_to_seq: i64,
+    ) -> Result<Vec<Message>, Error> {
+        let connection = self.connection()?;
+        live_query_results(&connection, query, after_seq, up_to_seq).map_err(|err| err.into())
+    }
+
+    pub fn table_row_counts(&self) -> Result<Vec<(String, i64)>, Error> {
+        let connection = self.connection()?;
+        table_row_counts(&connection).map_err(|err| err.into())
+    }
+
+    pub fn friends(&self, id: &str) -> Result<Vec<String>, Error> {
+        let connection = self.connection()?;
+        friends(&connection, id).map_err(|err| err.into())
+    }
+
+    pub fn search(&self, query: &str, limit: i64) -> Result<Vec<Message>, Error> {
+        let connection = self.connection()?;
+        search(&connection, query, limit).map_err(|err| err.into())
+    }
+
+    pub fn profile(&self, id: &str) -> Result<Profile, Error> {
+        let connection = self.connection()?;
+        profile(&connection, id).map_err(|err| err.into())
+    }
+
+    pub fn feed_integrity(&self, author: &str) -> Result<FeedIntegrity, Error> {
+        let connection = self.connection()?;
+        feed_integrity(&connection, author).map_err(|err| err.into())
+    }
+
+    pub fn forked_feeds(&self) -> Result<Vec<FeedFork>, Error> {
+        let connection = self.connection()?;
+        forked_feeds(&connection).map_err(|err| err.into())
+    }
+
+    pub fn feed_tips(&self, active_since: Option<f64>) -> Result<Vec<FeedTip>, Error> {
+        let connection = self.connection()?;
+        feed_tips(&connection, active_since).map_err(|err| err.into())
+    }
+
+    pub fn repos_by_author(&self, author: &str) -> Result<Vec<GitRepo>, Error> {
+        let connection = self.connection()?;
+        repos_by_author(&connection, author).map_err(|err| err.into())
+    }
+
+    pub fn repo_refs(&self, repo: &str) -> Result<Vec<GitRef>, Error> {
+        let connection = self.connection()?;
+        repo_refs(&connection, repo).map_err(|err| err.into())
+    }
+
+    pub fn open_issues(&self, repo: &str) -> Result<Vec<GitIssue>, Error> {
+        let connection = self.connection()?;
+        open_issues(&connection, repo).map_err(|err| err.into())
+    }
+
+    pub fn upcoming_gatherings(&self, after: f64, limit: i64) -> Result<Vec<Gathering>, Error> {
+        let connection = self.connection()?;
+        upcoming_gatherings(&connection, after, limit).map_err(|err| err.into())
+    }
+
+    pub fn gathering_attendees(&self, gathering: &str) -> Result<Vec<String>, Error> {
+        let connection = self.connection()?;
+        gathering_attendees(&connection, gathering).map_err(|err| err.into())
+    }
+
+    pub fn ranked_pubs(&self, id: &str) -> Result<Vec<RankedPub>, Error> {
+        let connection = self.connection()?;
+        ranked_pubs(&connection, id).map_err(|err| err.into())
+    }
+
+    pub fn my_tags(&self) -> Result<Vec<Tag>, Error> {
+        let connection = self.connection()?;
+        my_tags(&connection).map_err(|err| err.into())
+    }
+
+    pub fn messages_with_tag(&self, tag: &str) -> Result<Vec<Message>, Error> {
+        let connection = self.connection()?;
+        messages_with_tag(&connection, tag).map_err(|err| err.into())
+    }
+
+    pub fn tags_on_message(&self, message: &str) -> Result<Vec<Tag>, Error> {
+        let connection = self.connection()?;
+        tags_on_message(&connection, message).map_err(|err| err.into())
+    }
+
+    pub fn blobs_within_hops(&self, id: &str, hops: i64) -> Result<Vec<Blob>, Error> {
+        let connection = self.connection()?;
+        blobs_within_hops(&connection, id, hops).map_err(|err| err.into())
+    }
+
+    pub fn my_blobs(&self) -> Result<Vec<Blob>, Error> {
+        let connection = self.connection()?;
+        my_blobs(&connection).map_err(|err| err.into())
+    }
+
+    pub fn blob_report(
+        &self,
+        id: &str,
+        hops: i64,
+        local_blobs: &[String],
+    ) -> Result<BlobReport, Error> {
+        let connection = self.connection()?;
+        blob_report(&connection, id, hops, local_blobs)
+    }
+
+    pub fn hidden_content(&self) -> Result<Vec<HiddenContent>, Error> {
+        let connection = self.connection()?;
+        get_hidden_content(&connection).map_err(|err| err.into())
+    }
+
+    pub fn public_feed(
+        &self,
+        order: FeedOrder,
+        before: Option<&str>,
+        after: Option<&str>,
+        limit: i64,
+    ) -> Result<FeedPage, Error> {
+        let before = parse_cursor(before)?;
+        let after = parse_cursor(after)?;
+        let connection = self.connection()?;
+        public_feed(&connection, order, before.as_ref(), after.as_ref(), limit)
+            .map_err(|err| err.into())
+    }
+
+    pub fn author_feed(
+        &self,
+        author: &str,
+        order: FeedOrder,
+        before: Option<&str>,
+        after: Option<&str>,
+        limit: i64,
+    ) -> Result<FeedPage, Error> {
+        let before = parse_cursor(before)?;
+        let after = parse_cursor(after)?;
+        let connection = self.connection()?;
+        author_feed(
+            &connection,
+            author,
+            order,
+            before.as_ref(),
+            after.as_ref(),
+            limit,
+        )
+        .map_err(|err| err.into())
+    }
+
+    pub fn type_feed(
+        &self,
+        content_type: &str,
+        order: FeedOrder,
+        before: Option<&str>,
+        after: Option<&str>,
+        limit: i64,
+    ) -> Result<FeedPage, Error> {
+        let before = parse_cursor(before)?;
+        let after = parse_cursor(after)?;
+        let connection = self.connection()?;
+        type_feed(
+            &connection,
+            content_type,
+            order,
+            before.as_ref(),
+            after.as_ref(),
+            limit,
+        )
+        .map_err(|err| err.into())
+    }
+
+    pub fn mentions_feed(
+        &self,
+        author: &str,
+        order: FeedOrder,
+        before: Option<&str>,
+        after: Option<&str>,
+        limit: i64,
+    ) -> Result<FeedPage, Error> {
+        let before = parse_cursor(before)?;
+        let after = parse_cursor(after)?;
+        let connection = self.connection()?;
+        mentions_feed(
+            &connection,
+            author,
+            order,
+            before.as_ref(),
+            after.as_ref(),
+            limit,
+        )
+        .map_err(|err| err.into())
+    }
+
+    pub fn export<W: Write>(
+        &self,
+        table: ExportTable,
+        filter: &ExportFilter,
+        format: ExportFormat,
+        writer: &mut W,
+    ) -> Result<usize, Error> {
+        let connection = self.connection()?;
+        export_table(&connection, table, filter, format, writer)
+    }
+
+    pub fn export_offset_log(&self, filter: &ExportFilter, log_path: &str) -> Result<usize, Error> {
+        let connection = self.connection()?;
+        export_offset_log(&connection, filter, log_path)
+    }
+}
+
+fn parse_cursor(cursor: Option<&str>) -> Result<Option<FeedCursor>, Error> {
+    match cursor {
+        Some(cursor) => cursor.parse().map(Some),
+        None => Ok(None),
+    }
+}
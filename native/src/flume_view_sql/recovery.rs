@@ -0,0 +1,70 @@
+use failure::Error;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RebuildReason {
+    OutOfDate,
+    FailedIntegrityCheck,
+    // The db couldn't be read on open with `check_integrity_on_open` set, like when its header
+    // is corrupt. It's moved aside too.
+    Unreadable,
+    Requested,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RebuildEvent {
+    pub reason: RebuildReason,
+    pub db_path: String,
+    pub moved_to: Option<String>,
+    pub timestamp: u64,
+}
+
+impl RebuildEvent {
+    pub fn new(reason: RebuildReason, db_path: &str, moved_to: Option<String>) -> RebuildEvent {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+
+        RebuildEvent {
+            reason,
+            db_path: db_path.to_string(),
+            moved_to,
+            timestamp,
+        }
+    }
+
+    pub fn emit(&self) {
+        match serde_json::to_string(self) {
+            Ok(json) => warn!("sql view rebuild: {}", json),
+            Err(_) => warn!("sql view rebuild: {:?}", self),
+        }
+    }
+}
+
+// Moves a corrupted db out of the way rather than deleting it so it can still be inspected.
+pub fn move_db_aside(path: &str) -> Result<String, Error> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+
+    let moved_to = format!("{}.corrupt-{}", path, timestamp);
+    std::fs::rename(path, &moved_to)?;
+
+    for suffix in &["-wal", "-shm"] {
+        let sidecar = format!("{}{}", path, suffix);
+        if std::path::Path::new(&sidecar).exists() {
+            std::fs::rename(&sidecar, format!("{}{}", moved_to, suffix))?;
+        }
+    }
+
+    Ok(moved_to)
+}
+
+pub fn remove_db_sidecar_files(path: &str) {
+    for suffix in &["-wal", "-shm"] {
+        std::fs::remove_file(format!("{}{}", path, suffix)).unwrap_or(());
+    }
+}
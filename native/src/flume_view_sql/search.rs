@@ -0,0 +1,26 @@
+use flume_view_sql::*;
+use rusqlite::{Connection, Error, NO_PARAMS};
+
+pub fn create_search_tables(connection: &Connection) -> Result<usize, Error> {
+    trace!("Creating search tables");
+
+    // The rowid of each row is the key_id of the message the text came from.
+    connection.execute(
+        "CREATE VIRTUAL TABLE IF NOT EXISTS search_fts USING fts5(text)",
+        NO_PARAMS,
+    )
+}
+
+pub fn insert_search_text(
+    connection: &Connection,
+    message: &SsbMessage,
+    message_key_id: i64,
+) -> Result<(), Error> {
+    // A message appended twice replaces its text rather than failing the whole batch.
+    if let Value::String(text) = &message.value.content["text"] {
+        connection
+            .prepare_cached("INSERT OR REPLACE INTO search_fts (rowid, text) VALUES (?, ?)")?
+            .execute(&[&message_key_id as &ToSql, text])?;
+    }
+    Ok(())
+}
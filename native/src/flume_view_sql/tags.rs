@@ -0,0 +1,114 @@
+use flume_view_sql::*;
+use rusqlite::types::ToSql;
+use rusqlite::{Connection, Error, NO_PARAMS};
+
+// `tag` messages with a `name` create a tag. Ones with a `root` tag and a `message` add it to the
+// message, or remove it from the message when `tagged` is false.
+pub fn insert_tags(
+    connection: &Connection,
+    message: &SsbMessage,
+    message_key_id: i64,
+) -> Result<(), Error> {
+    let content = &message.value.content;
+
+    if content["type"].as_str() != Some("tag") {
+        return Ok(());
+    }
+
+    let author_id = find_or_create_author(connection, &message.value.author)?;
+
+    match (content["root"].as_str(), content["message"].as_str()) {
+        (Some(tag), Some(tagged_message)) => {
+            let tag_key_id = find_or_create_key(connection, tag)?;
+            let tagged_key_id = find_or_create_key(connection, tagged_message)?;
+            let is_tagged = content["tagged"].as_bool().unwrap_or(true);
+
+            connection
+                .prepare_cached("INSERT OR IGNORE INTO tagged_messages (tag_key_id, message_key_id, author_id, is_tagged, asserted_time) VALUES (?, ?, ?, ?, ?)")?
+                .execute(&[
+                    &tag_key_id as &ToSql,
+                    &tagged_key_id,
+                    &author_id,
+                    &is_tagged,
+                    &message.value.timestamp,
+                ])?;
+
+            // Messages don't always arrive in order, so keep whichever is newest.
+            connection
+                .prepare_cached(
+                    "
+                    UPDATE tagged_messages SET is_tagged = ?, asserted_time = ?
+                    WHERE tag_key_id = ? AND message_key_id = ? AND author_id = ?
+                    AND asserted_time <= ?
+                    ",
+                )?
+                .execute(&[
+                    &is_tagged as &ToSql,
+                    &message.value.timestamp,
+                    &tag_key_id,
+                    &tagged_key_id,
+                    &author_id,
+                    &message.value.timestamp,
+                ])?;
+        }
+        _ => {
+            if let Some(name) = content["name"].as_str() {
+                connection
+                    .prepare_cached(
+                        "INSERT INTO tags (key_id, author_id, name, asserted_time) VALUES (?, ?, ?, ?)",
+                    )?
+                    .execute(&[
+                        &message_key_id as &ToSql,
+                        &author_id,
+                        &name,
+                        &message.value.timestamp,
+                    ])?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub fn create_tags_tables(connection: &Connection) -> Result<usize, Error> {
+    trace!("Creating tags tables");
+
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS tags (
+          key_id INTEGER PRIMARY KEY,
+          author_id INTEGER,
+          name TEXT,
+          asserted_time REAL
+        )",
+        NO_PARAMS,
+    )?;
+
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS tagged_messages (
+          id INTEGER PRIMARY KEY,
+          tag_key_id INTEGER,
+          message_key_id INTEGER,
+          author_id INTEGER,
+          is_tagged BOOLEAN,
+          asserted_time REAL
+        )",
+        NO_PARAMS,
+    )
+}
+
+pub fn create_tags_indices(connection: &Connection) -> Result<usize, Error> {
+    trace!("Creating tags indices");
+
+    connection.execute(
+        "CREATE INDEX IF NOT EXISTS tags_author_id_index on tags (author_id)",
+        NO_PARAMS,
+    )?;
+    connection.execute(
+        "CREATE UNIQUE INDEX IF NOT EXISTS tagged_messages_tag_message_author_index on tagged_messages (tag_key_id, message_key_id, author_id)",
+        NO_PARAMS,
+    )?;
+    connection.execute(
+        "CREATE INDEX IF NOT EXISTS tagged_messages_message_key_id_index on tagged_messages (message_key_id)",
+        NO_PARAMS,
+    )
+}
@@ -0,0 +1,250 @@
+use base64::decode;
+use serde_json::{Map, Value};
+use sodiumoxide::crypto::hash::sha256;
+use sodiumoxide::crypto::sign::ed25519::{verify_detached, PublicKey, Signature};
+
+#[derive(Debug, Fail, PartialEq)]
+pub enum ValidationError {
+    #[fail(display = "Message value is missing {}", field)]
+    MissingField { field: &'static str },
+    #[fail(display = "Invalid author: {}", author)]
+    InvalidAuthor { author: String },
+    #[fail(display = "Invalid signature: {}", signature)]
+    InvalidSignature { signature: String },
+    #[fail(display = "Signature doesn't match the message")]
+    SignatureMismatch {},
+    #[fail(display = "Key {} isn't the hash of the message", key)]
+    HashMismatch { key: String },
+}
+
+// Checks the signature of a message value and that `key` is its hash. `value` must have been
+// parsed with its keys in their original order, as they're both over the serialized value.
+pub fn validate_message(key: &str, value: &Value) -> Result<(), ValidationError> {
+    let object = value
+        .as_object()
+        .ok_or(ValidationError::MissingField { field: "value" })?;
+
+    let author = object
+        .get("author")
+        .and_then(|author| author.as_str())
+        .ok_or(ValidationError::MissingField { field: "author" })?;
+
+    let signature = object
+        .get("signature")
+        .and_then(|signature| signature.as_str())
+        .ok_or(ValidationError::MissingField { field: "signature" })?;
+
+    let public_key = decode_tagged(author, "@", ".ed25519")
+        .and_then(|bytes| PublicKey::from_slice(&bytes))
+        .ok_or_else(|| ValidationError::InvalidAuthor {
+            author: author.to_string(),
+        })?;
+
+    let signature_bytes = decode_tagged(signature, "", ".sig.ed25519")
+        .and_then(|bytes| Signature::from_slice(&bytes))
+        .ok_or_else(|| ValidationError::InvalidSignature {
+            signature: signature.to_string(),
+        })?;
+
+    // The signature is over the value without its signature field, keeping the order of the rest.
+    let unsigned: Map<String, Value> = object
+        .iter()
+        .filter(|(name, _)| name.as_str() != "signature")
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect();
+
+    if !verify_detached(
+        &signature_bytes,
+        canonical_json(&Value::Object(unsigned)).as_bytes(),
+        &public_key,
+    ) {
+        return Err(ValidationError::SignatureMismatch {});
+    }
+
+    let expected_key = format!(
+        "%{}.sha256",
+        base64::encode(&sha256::hash(&hash_bytes(&canonical_json(value))).0)
+    );
+
+    if key != expected_key {
+        return Err(ValidationError::HashMismatch {
+            key: key.to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+// The same as `JSON.stringify(value, null, 2)` in js. Strings are escaped the same way as
+// serde_json does, but js writes numbers differently, like `1e+21` for 1e21 and `1` for 1.0.
+fn canonical_json(value: &Value) -> String {
+    let mut json = String::new();
+    write_json(&mut json, value, 0);
+    json
+}
+
+fn write_json(json: &mut String, value: &Value, depth: usize) {
+    match value {
+        Value::Number(number) => json.push_str(&js_number(number.as_f64().unwrap_or_default())),
+        Value::Array(values) if !values.is_empty() => {
+            json.push('[');
+            for (index, value) in values.iter().enumerate() {
+                if index > 0 {
+                    json.push(',');
+                }
+                write_indent(json, depth + 1);
+                write_json(json, value, depth + 1);
+            }
+            write_indent(json, depth);
+            json.push(']');
+        }
+        Value::Object(object) if !object.is_empty() => {
+            json.push('{');
+            for (index, (name, value)) in object.iter().enumerate() {
+                if index > 0 {
+                    json.push(',');
+                }
+                write_indent(json, depth + 1);
+                json.push_str(&serde_json::to_string(name).unwrap_or_default());
+                json.push_str(": ");
+                write_json(json, value, depth + 1);
+            }
+            write_indent(json, depth);
+            json.push('}');
+        }
+        value => json.push_str(&value.to_string()),
+    }
+}
+
+fn write_indent(json: &mut String, depth: usize) {
+    json.push('\n');
+    json.push_str(&"  ".repeat(depth));
+}
+
+// Rust and js both use the shortest digits that read back as the same number, so only where the
+// decimal point goes and when to use an exponent differ.
+fn js_number(number: f64) -> String {
+    if number == 0.0 {
+        return "0".to_string();
+    }
+
+    let scientific = format!("{:e}", number.abs());
+    let mut parts = scientific.splitn(2, 'e');
+    let digits: String = parts
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| *c != '.')
+        .collect();
+    let exponent: i32 = parts
+        .next()
+        .and_then(|exponent| exponent.parse().ok())
+        .unwrap_or(0);
+    let digits = round_half_to_even(number.abs(), digits);
+
+    let num_digits = digits.len() as i32;
+    let point = exponent + 1;
+    let formatted = if num_digits <= point && point <= 21 {
+        format!("{}{}", digits, "0".repeat((point - num_digits) as usize))
+    } else if 0 < point && point <= 21 {
+        format!(
+            "{}.{}",
+            &digits[..point as usize],
+            &digits[point as usize..]
+        )
+    } else if -6 < point && point <= 0 {
+        format!("0.{}{}", "0".repeat(-point as usize), digits)
+    } else {
+        let fraction = if num_digits > 1 {
+            format!(".{}", &digits[1..])
+        } else {
+            String::new()
+        };
+        let sign = if exponent >= 0 { "+" } else { "-" };
+        format!("{}{}e{}{}", &digits[..1], fraction, sign, exponent.abs())
+    };
+
+    if number < 0.0 {
+        format!("-{}", formatted)
+    } else {
+        formatted
+    }
+}
+
+// When a number is exactly between the two shortest ways to write it, js picks the one ending in
+// an even digit where rust rounds up.
+fn round_half_to_even(number: f64, digits: String) -> String {
+    let last = digits.as_bytes()[digits.len() - 1];
+    if last % 2 == 0 {
+        return digits;
+    }
+
+    let mut even = digits[..digits.len() - 1].to_string();
+    even.push((last - 1) as char);
+
+    // Every f64 can be written exactly in 767 significant digits.
+    let exact: String = format!("{:.767e}", number)
+        .chars()
+        .take_while(|c| *c != 'e')
+        .filter(|c| *c != '.')
+        .collect();
+    let (start, rest) = exact.split_at(digits.len());
+    if start == even && rest.starts_with('5') && rest[1..].chars().all(|c| c == '0') {
+        even
+    } else {
+        digits
+    }
+}
+
+// Message keys are the hash of the serialized value encoded as latin1, which keeps the low byte of
+// each utf-16 code unit.
+fn hash_bytes(json: &str) -> Vec<u8> {
+    json.encode_utf16().map(|unit| unit as u8).collect()
+}
+
+fn decode_tagged(id: &str, prefix: &str, suffix: &str) -> Option<Vec<u8>> {
+    if !id.starts_with(prefix) || !id.ends_with(suffix) || id.len() < prefix.len() + suffix.len() {
+        return None;
+    }
+
+    decode(&id[prefix.len()..id.len() - suffix.len()]).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn numbers_are_serialized_like_js() {
+        let numbers = [
+            (1.0, "1"),
+            (1.5, "1.5"),
+            (1e21, "1e+21"),
+            (1.25e22, "1.25e+22"),
+            (1e20, "100000000000000000000"),
+            (1e-7, "1e-7"),
+            (0.000001, "0.000001"),
+            (-2.5e-10, "-2.5e-10"),
+            (0.1 + 0.2, "0.30000000000000004"),
+            (1511493763121.6562, "1511493763121.6562"),
+        ];
+
+        for (number, expected) in numbers.iter() {
+            assert_eq!(js_number(*number), *expected);
+        }
+    }
+
+    #[test]
+    fn validate_message_with_floats() {
+        ::sodiumoxide::init().unwrap();
+
+        // Signed in js. `2` is written as `2.0` here, which is the same value.
+        let item = r#####"{"key":"%nhiC87KMroN6KgYk6LBmsrTuvYJwiimh/r69XqT5UWg=.sha256","value":{"previous":null,"author":"@EzLB8HvSnOUVfVODCJtVcTBkYxbSWZp1oiuVWhLqZQ4=.ed25519","sequence":1,"timestamp":1543958997985.25,"hash":"sha256","content":{"type":"post","text":"numbers","numbers":[1.5,1e+21,1.25e+22,1e-7,0.1,-2.5e-10,100000000000000000000,0.30000000000000004,2.0,1511493763121.6562]},"signature":"QawyaC81HhPPuUnPpAnwYtGmszqohvkeZ5eNUp/ywVyL3jOG4sb5zem8vZINqQ5AL+i0vCA3FUnjESpnKVxfBA==.sig.ed25519"},"timestamp":1543959001933}"#####;
+
+        let item: Value = serde_json::from_str(item).unwrap();
+        assert_eq!(
+            validate_message(item["key"].as_str().unwrap(), &item["value"]),
+            Ok(())
+        );
+    }
+}
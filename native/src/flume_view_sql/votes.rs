@@ -15,27 +15,26 @@ pub fn create_votes_tables(connection: &Connection) -> Result<usize, Error> {
     )
 }
 
-pub fn insert_or_update_votes(connection: &Connection, message: &SsbMessage) {
+pub fn insert_or_update_votes(connection: &Connection, message: &SsbMessage) -> Result<(), Error> {
     if let Value::Number(value) = &message.value.content["vote"]["value"] {
         if let Value::String(link) = &message.value.content["vote"]["link"] {
-            let author_id = find_or_create_author(&connection, &message.value.author).unwrap();
-            let link_to_key_id = find_or_create_key(connection, link).unwrap();
+            let author_id = find_or_create_author(&connection, &message.value.author)?;
+            let link_to_key_id = find_or_create_key(connection, link)?;
 
-            if value.as_i64().unwrap() == 1 {
+            if value.as_i64() == Some(1) {
                 connection
-                    .prepare_cached("INSERT INTO votes_raw (link_from_author_id, link_to_key_id) VALUES (?, ?)")
-                    .unwrap()
-                    .execute(&[&author_id, &link_to_key_id])
-                    .unwrap();
+                    .prepare_cached(
+                        "INSERT INTO votes_raw (link_from_author_id, link_to_key_id) VALUES (?, ?)",
+                    )?
+                    .execute(&[&author_id, &link_to_key_id])?;
             } else {
                 connection
-                    .prepare_cached("DELETE FROM votes_raw WHERE link_from_author_id = ? AND link_to_key_id = ?")
-                    .unwrap()
-                    .execute(&[&author_id, &link_to_key_id])
-                    .unwrap();
+                    .prepare_cached("DELETE FROM votes_raw WHERE link_from_author_id = ? AND link_to_key_id = ?")?
+                    .execute(&[&author_id, &link_to_key_id])?;
             }
         }
     }
+    Ok(())
 }
 
 pub fn create_votes_indices(connection: &Connection) -> Result<usize, Error> {
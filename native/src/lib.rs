@@ -16,28 +16,174 @@ extern crate base64;
 extern crate flumedb;
 extern crate node_napi;
 extern crate private_box;
+extern crate r2d2;
+extern crate r2d2_sqlite;
 extern crate rusqlite;
+extern crate sodiumoxide;
 
 use failure::Error;
 
-use itertools::Itertools;
 use node_napi::napi::*;
 use node_napi::napi_sys::*;
+use serde::Serialize;
 use std::debug_assert;
-use std::os::raw::c_void;
+use std::ffi::CString;
+use std::os::raw::{c_char, c_void};
 use std::ptr::{null, null_mut};
 use std::slice;
 
-use flumedb::OffsetLogIter;
 use flumedb::Sequence;
 
 use private_box::SecretKey;
 
 pub mod flume_view_sql;
 pub use flume_view_sql::FlumeViewSql;
+use flume_view_sql::{FlumeViewSqlError, SqlReader};
+
+#[derive(Debug, Fail)]
+pub enum NapiError {
+    #[fail(display = "Expected argument `{}` to be a {}", name, expected)]
+    InvalidArgumentType {
+        name: &'static str,
+        expected: &'static str,
+    },
+    #[fail(display = "Secret key is not a valid private box secret key")]
+    InvalidSecretKey {},
+    #[fail(display = "Method called on an object that isn't a SqlView")]
+    InvalidThis {},
+    #[fail(display = "{} failed", call)]
+    CallFailed { call: &'static str },
+}
+
+// The `code` property of the js error thrown for `err`. These are part of the js api so don't
+// change existing ones.
+fn error_code(err: &Error) -> &'static str {
+    if let Some(err) = err.downcast_ref::<NapiError>() {
+        return match err {
+            NapiError::InvalidArgumentType { .. } => "ERR_INVALID_ARG_TYPE",
+            NapiError::InvalidSecretKey {} => "ERR_INVALID_SECRET_KEY",
+            NapiError::InvalidThis {} => "ERR_INVALID_THIS",
+            NapiError::CallFailed { .. } => "ERR_NAPI",
+        };
+    }
+    if let Some(err) = err.downcast_ref::<FlumeViewSqlError>() {
+        return match err {
+            FlumeViewSqlError::DbFailedIntegrityCheck {} => "ERR_DB_INTEGRITY",
+            FlumeViewSqlError::DuplicatePluginName { .. } => "ERR_DUPLICATE_PLUGIN",
+            FlumeViewSqlError::MalformedMessage { .. } => "ERR_MALFORMED_MESSAGE",
+        };
+    }
+    if err.downcast_ref::<rusqlite::Error>().is_some() {
+        return "ERR_SQLITE";
+    }
+    if err.downcast_ref::<std::io::Error>().is_some() {
+        return "ERR_IO";
+    }
+
+    "ERR_SSB_SQL"
+}
+
+fn throw_js_error(env: napi_env, err: &Error) {
+    let code = CString::new(error_code(err)).unwrap();
+    let message = CString::new(err.to_string().replace('\0', ""))
+        .unwrap_or_else(|_| CString::new("Unknown error").unwrap());
+
+    let status = unsafe { napi_throw_error(env, code.as_ptr(), message.as_ptr()) };
+    debug_assert!(status == napi_status_napi_ok);
+}
+
+fn check_status(status: napi_status, call: &'static str) -> Result<(), Error> {
+    if status != napi_status_napi_ok {
+        return Err(NapiError::CallFailed { call }.into());
+    }
+
+    Ok(())
+}
+
+fn check_arg_type(
+    env: napi_env,
+    value: napi_value,
+    name: &'static str,
+    expected_type: napi_valuetype,
+    expected: &'static str,
+) -> Result<(), Error> {
+    let mut value_type: napi_valuetype = napi_valuetype_napi_undefined;
+    let status = unsafe { napi_typeof(env, value, &mut value_type) };
+
+    if status != napi_status_napi_ok || value_type != expected_type {
+        return Err(NapiError::InvalidArgumentType { name, expected }.into());
+    }
+
+    Ok(())
+}
+
+fn get_string_arg(
+    env: napi_env,
+    info: napi_callback_info,
+    index: usize,
+    name: &'static str,
+) -> Result<String, Error> {
+    let value = get_arg(env, info, index);
+    check_arg_type(env, value, name, napi_valuetype_napi_string, "string")?;
+
+    get_string(env, value).map_err(|_| {
+        NapiError::InvalidArgumentType {
+            name,
+            expected: "string",
+        }
+        .into()
+    })
+}
+
+fn get_int_arg(
+    env: napi_env,
+    info: napi_callback_info,
+    index: usize,
+    name: &'static str,
+) -> Result<i64, Error> {
+    let value = get_arg(env, info, index);
+    check_arg_type(env, value, name, napi_valuetype_napi_number, "number")?;
+
+    Ok(wrap_unsafe_get(env, value, napi_get_value_int64))
+}
+
+fn get_buffer_arg<'a>(
+    env: napi_env,
+    info: napi_callback_info,
+    index: usize,
+    name: &'static str,
+) -> Result<&'a [u8], Error> {
+    let value = get_arg(env, info, index);
+
+    let mut is_buffer = false;
+    let status = unsafe { napi_is_buffer(env, value, &mut is_buffer) };
+
+    if status != napi_status_napi_ok || !is_buffer {
+        return Err(NapiError::InvalidArgumentType {
+            name,
+            expected: "buffer",
+        }
+        .into());
+    }
+
+    let raw_parts = get_buffer_info(env, value);
+    Ok(unsafe { slice::from_raw_parts(raw_parts.0, raw_parts.1) })
+}
+
+// An empty buffer means there are no keys to decrypt with. Anything else has to be a valid key.
+fn secret_keys_from_bytes(secret_key_bytes: &[u8]) -> Result<Vec<SecretKey>, Error> {
+    if secret_key_bytes.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    SecretKey::from_slice(secret_key_bytes)
+        .map(|secret_key| vec![secret_key])
+        .ok_or_else(|| NapiError::InvalidSecretKey {}.into())
+}
 
 struct SsbQuery {
     view: FlumeViewSql,
+    reader: SqlReader,
     log_path: String,
 }
 
@@ -49,95 +195,188 @@ impl SsbQuery {
         pub_key: &str,
     ) -> Result<SsbQuery, Error> {
         let view = FlumeViewSql::new(&view_path, keys, pub_key)?;
+        let reader = view.reader()?;
 
-        Ok(SsbQuery { view, log_path })
+        Ok(SsbQuery {
+            view,
+            reader,
+            log_path,
+        })
     }
 
-    fn get_latest(&self) -> Sequence {
-        self.view.get_latest().unwrap()
+    fn get_latest(&self) -> Result<Sequence, Error> {
+        self.view.get_latest()
     }
 
-    fn process(&mut self, num_items: i64) {
-        let latest = self.get_latest();
+    fn process(&mut self, num_items: i64) -> Result<(), Error> {
+        self.view.process(&self.log_path, num_items)?;
+        self.view.catch_up_plugins(num_items).map(|_| ())
+    }
+}
 
-        //If the latest is 0, we haven't got anything in the db. Don't skip the very first
-        //element in the offset log. I know this isn't super nice. It could be refactored later.
-        let num_to_skip = match latest {
-            0 => 0,
-            _ => 1,
-        };
-        let log_path = self.log_path.clone();
-        let file = std::fs::File::open(log_path.clone()).unwrap();
+fn get_ssb_query<'a>(env: napi_env, info: napi_callback_info) -> Result<&'a mut SsbQuery, Error> {
+    let this = get_this(env, info);
+    let mut ptr_ssb_query = null_mut();
 
-        let items_to_take = match num_items {
-            -1 => std::usize::MAX,
-            n => n as usize,
-        };
+    let status = unsafe { napi_unwrap(env, this, &mut ptr_ssb_query) };
 
-        OffsetLogIter::<u32>::with_starting_offset(file, latest)
-            .skip(num_to_skip)
-            .take(items_to_take)
-            .map(|data| (data.offset + latest, data.data)) //TODO log_latest might not be the right thing
-            .chunks(1000)
-            .into_iter()
-            .for_each(|chunk| {
-                self.view.append_batch(&chunk.collect_vec());
-            })
+    if status != napi_status_napi_ok || ptr_ssb_query.is_null() {
+        return Err(NapiError::InvalidThis {}.into());
     }
+
+    Ok(unsafe { &mut *(ptr_ssb_query as *mut SsbQuery) })
 }
 
-#[no_mangle]
-extern "C" fn get_latest(env: napi_env, info: napi_callback_info) -> napi_value {
-    let this = get_this(env, info);
-    let mut ptr_ssb_query = null_mut();
+// Converts query results to js objects by serializing them to json and calling `JSON.parse`.
+fn create_js_value<T: Serialize>(env: napi_env, value: &T) -> Result<napi_value, Error> {
+    let json = serde_json::to_string(value)?;
+    let json_string = create_string_utf8(env, &json);
+
+    let mut global = null_mut();
+    let mut json_object = null_mut();
+    let mut parse = null_mut();
+    let mut result = null_mut();
+
+    unsafe {
+        check_status(napi_get_global(env, &mut global), "napi_get_global")?;
+        check_status(
+            napi_get_named_property(
+                env,
+                global,
+                "JSON\0".as_ptr() as *const c_char,
+                &mut json_object,
+            ),
+            "napi_get_named_property",
+        )?;
+        check_status(
+            napi_get_named_property(
+                env,
+                json_object,
+                "parse\0".as_ptr() as *const c_char,
+                &mut parse,
+            ),
+            "napi_get_named_property",
+        )?;
+        check_status(
+            napi_call_function(env, json_object, parse, 1, &json_string, &mut result),
+            "napi_call_function",
+        )?;
+    }
 
-    unsafe { napi_unwrap(env, this, &mut ptr_ssb_query) };
+    Ok(result)
+}
 
-    let ssb_query = ptr_ssb_query as *mut SsbQuery;
-    let latest = unsafe { (*ssb_query).get_latest() };
+// Every exported function goes through here so that errors become js exceptions instead of
+// aborting the process.
+fn return_result(env: napi_env, result: Result<napi_value, Error>) -> napi_value {
+    match result {
+        Ok(value) => value,
+        Err(err) => {
+            throw_js_error(env, &err);
+            get_undefined_value(env)
+        }
+    }
+}
 
-    wrap_unsafe_create::<i64>(env, latest as i64, napi_create_int64)
+#[no_mangle]
+extern "C" fn get_latest(env: napi_env, info: napi_callback_info) -> napi_value {
+    let result = get_ssb_query(env, info)
+        .and_then(|ssb_query| ssb_query.get_latest())
+        .map(|latest| wrap_unsafe_create::<i64>(env, latest as i64, napi_create_int64));
+
+    return_result(env, result)
 }
 
 #[no_mangle]
 extern "C" fn process(env: napi_env, info: napi_callback_info) -> napi_value {
-    let this = get_this(env, info);
+    let result = get_ssb_query(env, info).and_then(|ssb_query| {
+        let num = get_int_arg(env, info, 0, "chunkSize")?;
+        ssb_query.process(num)?;
+        Ok(get_undefined_value(env))
+    });
 
-    let num_value = get_arg(env, info, 0);
-    let num = wrap_unsafe_get(env, num_value, napi_get_value_int64);
+    return_result(env, result)
+}
 
-    let mut ptr_ssb_query = null_mut();
+extern "C" fn backlinks(env: napi_env, info: napi_callback_info) -> napi_value {
+    let result = get_ssb_query(env, info).and_then(|ssb_query| {
+        let id = get_string_arg(env, info, 0, "id")?;
+        let links = ssb_query.reader.back_link_references(&id, 0.0)?;
+        create_js_value(env, &links)
+    });
 
-    unsafe { napi_unwrap(env, this, &mut ptr_ssb_query) };
+    return_result(env, result)
+}
 
-    let ssb_query = ptr_ssb_query as *mut SsbQuery;
-    unsafe { (*ssb_query).process(num) };
+extern "C" fn thread(env: napi_env, info: napi_callback_info) -> napi_value {
+    let result = get_ssb_query(env, info).and_then(|ssb_query| {
+        let root = get_string_arg(env, info, 0, "rootId")?;
+        let messages = ssb_query.reader.thread(&root)?;
+        create_js_value(env, &messages)
+    });
 
-    get_undefined_value(env)
+    return_result(env, result)
 }
-#[no_mangle]
-pub extern "C" fn define_view_class(env: napi_env) -> napi_value {
-    let latest_property: napi_property_descriptor = napi_property_descriptor {
-        utf8name: null(),
-        name: create_string_utf8(env, "getLatest"),
-        method: Some(get_latest),
-        getter: None,
-        setter: None,
-        value: null_mut(),
-        attributes: napi_property_attributes_napi_default,
-        data: null_mut(),
-    };
-    let process_property: napi_property_descriptor = napi_property_descriptor {
+
+extern "C" fn friends(env: napi_env, info: napi_callback_info) -> napi_value {
+    let result = get_ssb_query(env, info).and_then(|ssb_query| {
+        let id = get_string_arg(env, info, 0, "id")?;
+        let friends = ssb_query.reader.friends(&id)?;
+        create_js_value(env, &friends)
+    });
+
+    return_result(env, result)
+}
+
+extern "C" fn search(env: napi_env, info: napi_callback_info) -> napi_value {
+    let result = get_ssb_query(env, info).and_then(|ssb_query| {
+        let query = get_string_arg(env, info, 0, "query")?;
+        let limit = get_int_arg(env, info, 1, "limit")?;
+        let messages = ssb_query.reader.search(&query, limit)?;
+        create_js_value(env, &messages)
+    });
+
+    return_result(env, result)
+}
+
+extern "C" fn profile(env: napi_env, info: napi_callback_info) -> napi_value {
+    let result = get_ssb_query(env, info).and_then(|ssb_query| {
+        let id = get_string_arg(env, info, 0, "id")?;
+        let profile = ssb_query.reader.profile(&id)?;
+        create_js_value(env, &profile)
+    });
+
+    return_result(env, result)
+}
+
+fn method_property(
+    env: napi_env,
+    name: &str,
+    method: unsafe extern "C" fn(napi_env, napi_callback_info) -> napi_value,
+) -> napi_property_descriptor {
+    napi_property_descriptor {
         utf8name: null(),
-        name: create_string_utf8(env, "process"),
-        method: Some(process),
+        name: create_string_utf8(env, name),
+        method: Some(method),
         getter: None,
         setter: None,
         value: null_mut(),
         attributes: napi_property_attributes_napi_default,
         data: null_mut(),
-    };
-    let properties = vec![latest_property, process_property];
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn define_view_class(env: napi_env) -> napi_value {
+    let properties = vec![
+        method_property(env, "getLatest", get_latest),
+        method_property(env, "process", process),
+        method_property(env, "backlinks", backlinks),
+        method_property(env, "thread", thread),
+        method_property(env, "friends", friends),
+        method_property(env, "search", search),
+        method_property(env, "profile", profile),
+    ];
     let data = null_mut();
 
     define_class(
@@ -159,51 +398,37 @@ pub extern "C" fn finalize_view(_: napi_env, data: *mut c_void, _: *mut c_void)
 pub extern "C" fn construct_view_class(env: napi_env, info: napi_callback_info) -> napi_value {
     let this = get_this(env, info);
 
-    let path_to_offset_value = get_arg(env, info, 0);
-    let path_to_db_value = get_arg(env, info, 1);
-    let secret_key_value = get_arg(env, info, 2);
-    let pub_key_value = get_arg(env, info, 3);
-
-    let raw_parts = get_buffer_info(env, secret_key_value);
+    let result = construct_ssb_query(env, info).and_then(|query| {
+        let ssb_query = Box::new(query);
+
+        let mut wrapped_ref: napi_ref = null_mut();
+        let finalize_hint: *mut c_void = null_mut();
+
+        let status = unsafe {
+            napi_wrap(
+                env,
+                this,
+                Box::into_raw(ssb_query) as *mut c_void,
+                Some(finalize_view),
+                finalize_hint,
+                &mut wrapped_ref,
+            )
+        };
 
-    let secret_key_bytes = unsafe { slice::from_raw_parts(raw_parts.0, raw_parts.1) };
-    let secret_key = SecretKey::from_slice(secret_key_bytes).unwrap_or_else(|| {
-        let empty_slice = vec![
-            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-            0, 0, 0,
-        ];
-        SecretKey::from_slice(&empty_slice[0..32]).unwrap()
+        debug_assert!(status == napi_status_napi_ok);
+        Ok(this)
     });
-    let keys = vec![secret_key];
-
-    let path_to_offset = get_string(env, path_to_offset_value).unwrap();
-    let path_to_db = get_string(env, path_to_db_value).unwrap();
-    let pub_key = get_string(env, pub_key_value).unwrap();
-
-    let mut wrapped_ref: napi_ref = null_mut();
-    let finalize_hint: *mut c_void = null_mut();
-
-    match SsbQuery::new(path_to_offset, path_to_db, keys, &pub_key) {
-        Ok(query) => {
-            let ssb_query = Box::new(query);
-
-            let status = unsafe {
-                napi_wrap(
-                    env,
-                    this,
-                    Box::into_raw(ssb_query) as *mut c_void,
-                    Some(finalize_view),
-                    finalize_hint,
-                    &mut wrapped_ref,
-                )
-            };
-
-            debug_assert!(status == napi_status_napi_ok);
-        }
-        Err(err) => {
-            throw_error(env, err);
-        }
-    }
 
-    this
+    return_result(env, result)
+}
+
+fn construct_ssb_query(env: napi_env, info: napi_callback_info) -> Result<SsbQuery, Error> {
+    let path_to_offset = get_string_arg(env, info, 0, "logPath")?;
+    let path_to_db = get_string_arg(env, info, 1, "dbPath")?;
+    let secret_key_bytes = get_buffer_arg(env, info, 2, "secretKey")?;
+    let pub_key = get_string_arg(env, info, 3, "pubKey")?;
+
+    let keys = secret_keys_from_bytes(secret_key_bytes)?;
+
+    SsbQuery::new(path_to_offset, path_to_db, keys, &pub_key)
 }
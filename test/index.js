@@ -35,6 +35,22 @@ test('db has method process ', function (t) {
   t.end()
 })
 
+test('db has typed query methods', function (t) {
+  var db = createTestDb()
+  ;['backlinks', 'thread', 'friends', 'search', 'profile'].forEach(function (method) {
+    t.equal(typeof (db[method]), 'function', method)
+  })
+  t.end()
+})
+
+test('typed queries return js objects', function (t) {
+  var db = createTestDb()
+  t.deepEqual(db.thread('%9EdpeKC5CgzpQs/x99CcnbD3n6ugUlwm19F7ZTqMh5w=.sha256'), [])
+  t.deepEqual(db.friends(''), [])
+  t.equal(db.profile('@U5GvOKP/YUza9k53DSXxT0mk3PIrnyAmessvNfZl5E0=.ed25519').name, null)
+  t.end()
+})
+
 test('create throws when paths are not strings', function (t) {
   t.throws(function () {
     Db(null, '', Buffer.from(''), '')
@@ -52,6 +68,25 @@ test('create throws when secretKey is not a buffer', function (t) {
   t.end()
 })
 
+test('create throws when secretKey is not a valid key', function (t) {
+  t.throws(function () {
+    Db('/tmp/test.offset', '/tmp/test.sqlite', Buffer.from('not a key'), '')
+  }, function (err) {
+    return err.code === 'ERR_INVALID_SECRET_KEY'
+  })
+  t.end()
+})
+
+test('errors from the native module have a code', function (t) {
+  var db = createTestDb()
+  t.throws(function () {
+    db.search('"unterminated', { limit: 10 })
+  }, function (err) {
+    return err.code === 'ERR_SQLITE'
+  })
+  t.end()
+})
+
 test('create throws when pub key is not a string', function (t) {
   t.throws(function () {
     Db('', '', Buffer.from(''))